@@ -0,0 +1,278 @@
+use crate::{
+    color::encode_srgb8,
+    texture::{MipLevel, Texels, Texture},
+};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Rectangle of a texture in texture coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+impl UvRect {
+    pub const FULL: UvRect = UvRect {
+        min: [0.0; 2],
+        max: [1.0; 2],
+    };
+    // Coordinate inside the rectangle, 0..1 spans it
+    pub fn map(&self, uv: [f32; 2]) -> [f32; 2] {
+        std::array::from_fn(|i| self.min[i] + uv[i] * (self.max[i] - self.min[i]))
+    }
+    // Same as map, but the rectangle repeats every whole coordinate
+    pub fn repeat(&self, uv: [f32; 2]) -> [f32; 2] {
+        self.map(uv.map(|c| c - c.floor()))
+    }
+    pub fn size(&self) -> [f32; 2] {
+        [self.max[0] - self.min[0], self.max[1] - self.min[1]]
+    }
+}
+
+// Collects named textures to pack into one atlas
+#[derive(Clone, Debug)]
+pub struct AtlasBuilder {
+    images: Vec<(String, Texture)>,
+    // Texels around every tile repeating its edge, a power of two
+    pub padding: u32,
+}
+impl Default for AtlasBuilder {
+    fn default() -> Self {
+        AtlasBuilder {
+            images: Vec::new(),
+            padding: 8,
+        }
+    }
+}
+impl AtlasBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+    // Only the full size image is packed, adding a name again replaces its texture
+    pub fn add(&mut self, name: &str, texture: &Texture) {
+        let texture = texture.clone();
+        match self.images.iter_mut().find(|(n, _)| n == name) {
+            Some((_, old)) => *old = texture,
+            None => self.images.push((name.to_string(), texture)),
+        }
+    }
+    pub fn with_texture(mut self, name: &str, texture: &Texture) -> Self {
+        self.add(name, texture);
+        self
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.images.iter().any(|(n, _)| n == name)
+    }
+    // Packs the images into rows of a power of two sized texture, tallest first. Cells are
+    // rounded up to a multiple of the padding, so tiles stay aligned to it, mip levels up to the
+    // padding never mix neighbouring tiles and smaller levels are dropped.
+    pub fn build(self) -> Atlas {
+        let pad = self.padding;
+        let round = |n: u32| n.next_multiple_of(pad.max(1));
+        let cell = |texture: &Texture| {
+            [
+                round(texture.width()) + 2 * pad,
+                round(texture.height()) + 2 * pad,
+            ]
+        };
+        let area: u32 = self
+            .images
+            .iter()
+            .map(|(_, texture)| cell(texture)[0] * cell(texture)[1])
+            .sum();
+        let widest = self.images.iter().map(|(_, t)| cell(t)[0]).max();
+        let width = ((area as f32).sqrt().ceil() as u32)
+            .max(widest.unwrap_or(1))
+            .next_power_of_two();
+
+        let mut order: Vec<usize> = (0..self.images.len()).collect();
+        order.sort_by_key(|&i| Reverse(self.images[i].1.height()));
+        let mut tiles = HashMap::new();
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for &i in &order {
+            let (name, texture) = &self.images[i];
+            let [w, h] = cell(texture);
+            if x + w > width {
+                (x, y, row_height) = (0, y + row_height, 0);
+            }
+            let tile = Tile {
+                x: x + pad,
+                y: y + pad,
+                width: texture.width(),
+                height: texture.height(),
+            };
+            tiles.insert(name.clone(), tile);
+            x += w;
+            row_height = row_height.max(h);
+        }
+        let height = (y + row_height).max(1).next_power_of_two();
+
+        // Color atlas, texels of sRGB images are copied as they are
+        let mut texels = vec![[0; 4]; (width * height) as usize];
+        for (name, texture) in &self.images {
+            let tile = tiles[name];
+            let level = &texture.levels[0];
+            let (w, h) = (tile.width as i64, tile.height as i64);
+            let [cell_w, cell_h] = cell(texture).map(|c| c as i64);
+            let pad = pad as i64;
+            for ty in -pad..cell_h - pad {
+                for tx in -pad..cell_w - pad {
+                    // Padding and the rest of the cell repeat the nearest edge texel
+                    let sx = tx.clamp(0, w - 1) as usize;
+                    let sy = ty.clamp(0, h - 1) as usize;
+                    let texel = match &level.texels {
+                        Texels::Srgba8(texels) => texels[sx + sy * w as usize],
+                        _ => {
+                            let [r, g, b, a] = level.texel(sx, sy);
+                            [
+                                encode_srgb8(r),
+                                encode_srgb8(g),
+                                encode_srgb8(b),
+                                (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                            ]
+                        }
+                    };
+                    let ax = (tile.x as i64 + tx) as usize;
+                    let ay = (tile.y as i64 + ty) as usize;
+                    texels[ax + ay * width as usize] = texel;
+                }
+            }
+        }
+        let mut texture = Texture::from_level(MipLevel {
+            texels: Texels::Srgba8(texels),
+            width,
+            height,
+        });
+        texture.levels.truncate(pad.max(1).ilog2() as usize + 1);
+        Atlas {
+            texture: Arc::new(texture),
+            tiles,
+        }
+    }
+}
+
+// Position of a packed image in texels of the full atlas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Many textures packed into one, so meshes using any of them can share a material
+#[derive(Clone, Debug)]
+pub struct Atlas {
+    pub texture: Arc<Texture>,
+    tiles: HashMap<String, Tile>,
+}
+impl Atlas {
+    pub fn tile(&self, name: &str) -> Option<Tile> {
+        self.tiles.get(name).copied()
+    }
+    pub fn rect(&self, name: &str) -> Option<UvRect> {
+        let tile = self.tile(name)?;
+        let (w, h) = (self.texture.width() as f32, self.texture.height() as f32);
+        Some(UvRect {
+            min: [tile.x as f32 / w, tile.y as f32 / h],
+            max: [
+                (tile.x + tile.width) as f32 / w,
+                (tile.y + tile.height) as f32 / h,
+            ],
+        })
+    }
+    // Copy of one packed image with its full mip chain
+    pub fn sub_texture(&self, name: &str) -> Option<Texture> {
+        let tile = self.tile(name)?;
+        Some(
+            self.texture
+                .sub_texture(tile.x, tile.y, tile.width, tile.height),
+        )
+    }
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> Texture {
+        let img = RgbaImage::from_pixel(width, height, Rgba(color));
+        Texture::from_srgb_image(DynamicImage::ImageRgba8(img))
+    }
+
+    #[test]
+    fn tiles_are_padded_and_separate() {
+        let atlas = AtlasBuilder::new()
+            .with_padding(4)
+            .with_texture("red", &solid(16, 16, [255, 0, 0, 255]))
+            .with_texture("green", &solid(16, 8, [0, 255, 0, 255]))
+            .with_texture("blue", &solid(32, 16, [0, 0, 255, 255]))
+            .build();
+        assert_eq!(atlas.len(), 3);
+        let full = &atlas.texture.levels[0];
+        let names = ["red", "green", "blue"];
+        for (i, name) in names.iter().enumerate() {
+            let tile = atlas.tile(name).unwrap();
+            // Tile and its padding have the color of the image
+            let color = full.texel((tile.x - 4) as usize, (tile.y - 4) as usize);
+            assert_eq!(color[i], 1.0);
+            let last = (tile.x + tile.width + 3, tile.y + tile.height + 3);
+            assert_eq!(full.texel(last.0 as usize, last.1 as usize), color);
+            for other in &names[i + 1..] {
+                let other = atlas.tile(other).unwrap();
+                let apart = tile.x + tile.width + 4 <= other.x - 4
+                    || other.x + other.width + 4 <= tile.x - 4
+                    || tile.y + tile.height + 4 <= other.y - 4
+                    || other.y + other.height + 4 <= tile.y - 4;
+                assert!(apart);
+            }
+        }
+        // Levels below the padding would mix tiles
+        assert_eq!(atlas.texture.levels.len(), 3);
+        let rect = atlas.rect("green").unwrap();
+        let center = rect.map([0.5, 0.5]);
+        assert_eq!(atlas.texture.get_pixel(center[0], center[1])[1], 1.0);
+        assert!(atlas.rect("missing").is_none());
+    }
+
+    #[test]
+    fn odd_sized_tiles_stay_aligned() {
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let sizes = [[5, 3], [7, 7], [9, 2]];
+        let mut builder = AtlasBuilder::new().with_padding(4);
+        for (i, (size, color)) in sizes.iter().zip(colors).enumerate() {
+            builder.add(&i.to_string(), &solid(size[0], size[1], color));
+        }
+        let atlas = builder.build();
+        assert_eq!(atlas.texture.levels.len(), 3);
+        for i in 0..sizes.len() {
+            let tile = atlas.tile(&i.to_string()).unwrap();
+            assert_eq!([tile.x % 4, tile.y % 4], [0, 0]);
+            // Every texel of every level that covers the tile has only its color
+            for (k, level) in atlas.texture.levels.iter().enumerate() {
+                let (x0, y0) = (tile.x >> k, tile.y >> k);
+                let x1 = (tile.x + tile.width).div_ceil(1 << k);
+                let y1 = (tile.y + tile.height).div_ceil(1 << k);
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        let texel = level.texel(x as usize, y as usize);
+                        assert_eq!(texel[i], 1.0);
+                        assert_eq!(texel[3], 1.0);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::{
+    error::{Error, Result},
+    voxel::{BlockId, AIR},
+};
+use std::fmt;
+use std::path::Path;
+
+// Image of one face of a block, either whole or one square tile of a horizontal strip like
+// the ones Object::cube uses
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FaceTexture {
+    pub path: String,
+    pub tile: Option<u32>,
+}
+impl FaceTexture {
+    pub fn new(path: &str) -> Self {
+        FaceTexture {
+            path: path.to_string(),
+            tile: None,
+        }
+    }
+    pub fn tile(path: &str, tile: u32) -> Self {
+        FaceTexture {
+            path: path.to_string(),
+            tile: Some(tile),
+        }
+    }
+    // Parses "file.png" or "file.png:3"
+    fn parse(value: &str) -> Self {
+        match value.rsplit_once(':') {
+            Some((path, tile)) if tile.parse::<u32>().is_ok() => {
+                FaceTexture::tile(path, tile.parse().unwrap())
+            }
+            _ => FaceTexture::new(value),
+        }
+    }
+}
+// Same form as parsed, used as the name of the texture in the atlas
+impl fmt::Display for FaceTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.tile {
+            Some(tile) => write!(f, "{}:{tile}", self.path),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockType {
+    pub name: String,
+    pub top: FaceTexture,
+    pub side: FaceTexture,
+    pub bottom: FaceTexture,
+    // Faces of neighbouring blocks are drawn behind it and its texture is alpha tested
+    pub transparent: bool,
+    // Milliseconds of mining it takes to break the block
+    pub hardness: f32,
+    // The player stands on solid blocks
+    pub solid: bool,
+    // Name of the block given when it's mined, none for nothing
+    pub drop: Option<String>,
+}
+impl BlockType {
+    // Opaque solid block with the same texture on every face, dropping itself
+    pub fn new(name: &str, texture: FaceTexture) -> Self {
+        BlockType {
+            name: name.to_string(),
+            top: texture.clone(),
+            side: texture.clone(),
+            bottom: texture,
+            transparent: false,
+            hardness: 1000.0,
+            solid: true,
+            drop: Some(name.to_string()),
+        }
+    }
+    pub fn with_faces(mut self, top: FaceTexture, side: FaceTexture, bottom: FaceTexture) -> Self {
+        self.top = top;
+        self.side = side;
+        self.bottom = bottom;
+        self
+    }
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+    pub fn with_hardness(mut self, hardness: f32) -> Self {
+        self.hardness = hardness;
+        self
+    }
+    pub fn with_solid(mut self, solid: bool) -> Self {
+        self.solid = solid;
+        self
+    }
+    pub fn with_drop(mut self, drop: Option<&str>) -> Self {
+        self.drop = drop.map(str::to_string);
+        self
+    }
+}
+
+// Every kind of block the voxel world can hold, block id is the index plus one
+#[derive(Clone, Debug, Default)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockType>,
+}
+impl BlockRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn register(&mut self, block: BlockType) -> BlockId {
+        self.blocks.push(block);
+        self.blocks.len() as BlockId
+    }
+    pub fn with_block(mut self, block: BlockType) -> Self {
+        self.register(block);
+        self
+    }
+    // None for air
+    pub fn get(&self, id: BlockId) -> Option<&BlockType> {
+        match id {
+            AIR => None,
+            id => self.blocks.get(id as usize - 1),
+        }
+    }
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.blocks
+            .iter()
+            .position(|block| block.name == name)
+            .map(|i| i as BlockId + 1)
+    }
+    // Block given by mining the block
+    pub fn drop(&self, id: BlockId) -> Option<BlockId> {
+        self.get(id)?.drop.as_deref().and_then(|name| self.id(name))
+    }
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &BlockType)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (i as BlockId + 1, block))
+    }
+    pub fn load(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_string(),
+            source,
+        })?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let blocks = Self::parse_blocks(&text, path, dir)?;
+        // Tiles have to fit in their strip, images that can't be read are left for the world to
+        // report and replace
+        for (line, block) in &blocks {
+            for face in [&block.top, &block.side, &block.bottom] {
+                let Some(tile) = face.tile else { continue };
+                let Ok((width, height)) = image::image_dimensions(&face.path) else {
+                    continue;
+                };
+                if (tile + 1) * height > width {
+                    return Err(Error::Parse {
+                        path: path.to_string(),
+                        line: line + 1,
+                        message: format!(
+                            "{} uses tile {tile} past the end of {}",
+                            block.name, face.path
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(Self::from_blocks(blocks))
+    }
+    // Reads sections like
+    //   [grass]
+    //   texture = dirt.png
+    //   top = grass.png:4
+    //   hardness = 600
+    // Texture paths are relative to dir, path is only used in errors.
+    pub fn parse(text: &str, path: &str, dir: &Path) -> Result<Self> {
+        Self::parse_blocks(text, path, dir).map(Self::from_blocks)
+    }
+    fn from_blocks(blocks: Vec<(usize, BlockType)>) -> Self {
+        BlockRegistry {
+            blocks: blocks.into_iter().map(|(_, block)| block).collect(),
+        }
+    }
+    // Validated blocks with the line of their header
+    fn parse_blocks(text: &str, path: &str, dir: &Path) -> Result<Vec<(usize, BlockType)>> {
+        let error = |line: usize, message: String| Error::Parse {
+            path: path.to_string(),
+            line: line + 1,
+            message,
+        };
+        let texture = |value: &str| {
+            let face = FaceTexture::parse(value);
+            FaceTexture {
+                path: dir.join(&face.path).to_string_lossy().into_owned(),
+                ..face
+            }
+        };
+        // Blocks with the line of their header and which faces got a texture
+        let mut blocks: Vec<(usize, BlockType, [bool; 3])> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if blocks.iter().any(|(_, block, _)| block.name == name) {
+                    return Err(error(i, format!("duplicate block {name}")));
+                }
+                let block = BlockType::new(name, FaceTexture::new(""));
+                blocks.push((i, block, [false; 3]));
+                continue;
+            }
+            let Some((_, block, faces)) = blocks.last_mut() else {
+                return Err(error(i, "property outside of a block".to_string()));
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error(i, format!("expected key = value, got {line}")));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let flag = |value: &str| {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| error(i, format!("{key} must be true or false")))
+            };
+            match key {
+                "texture" => {
+                    let face = texture(value);
+                    block.top = face.clone();
+                    block.side = face.clone();
+                    block.bottom = face;
+                    *faces = [true; 3];
+                }
+                "top" => (block.top, faces[0]) = (texture(value), true),
+                "side" => (block.side, faces[1]) = (texture(value), true),
+                "bottom" => (block.bottom, faces[2]) = (texture(value), true),
+                "transparent" => block.transparent = flag(value)?,
+                "solid" => block.solid = flag(value)?,
+                "hardness" => {
+                    // Also rejects NaN and infinity, which no mining time would ever reach
+                    block.hardness = value
+                        .parse()
+                        .ok()
+                        .filter(|hardness: &f32| hardness.is_finite() && *hardness >= 0.0)
+                        .ok_or_else(|| error(i, format!("invalid hardness {value}")))?
+                }
+                "drop" => block.drop = (value != "none").then(|| value.to_string()),
+                _ => return Err(error(i, format!("unknown property {key}"))),
+            }
+        }
+        for (line, block, faces) in &blocks {
+            if faces.contains(&false) {
+                return Err(error(*line, format!("{} is missing a texture", block.name)));
+            }
+            if let Some(drop) = &block.drop {
+                if !blocks.iter().any(|(_, other, _)| other.name == *drop) {
+                    return Err(error(
+                        *line,
+                        format!("{} drops unknown block {drop}", block.name),
+                    ));
+                }
+            }
+        }
+        Ok(blocks
+            .into_iter()
+            .map(|(line, block, _)| (line, block))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_blocks() {
+        let text = "
+            # comment
+            [grass]
+            texture = grass.png:0
+            top = grass.png:4
+            drop = dirt
+
+            [dirt]
+            texture = dirt.png
+            hardness = 500
+
+            [glass]
+            texture = glass.png
+            transparent = true
+            drop = none
+        ";
+        let registry = BlockRegistry::parse(text, "blocks.txt", Path::new("assets")).unwrap();
+        assert_eq!(registry.len(), 3);
+        let grass = registry.id("grass").unwrap();
+        let dirt = registry.id("dirt").unwrap();
+        let glass = registry.id("glass").unwrap();
+        let block = registry.get(grass).unwrap();
+        assert_eq!(block.top.tile, Some(4));
+        assert_eq!(block.side.tile, Some(0));
+        assert!(block.top.path.ends_with("grass.png"));
+        assert_eq!(registry.drop(grass), Some(dirt));
+        assert_eq!(registry.drop(dirt), Some(dirt));
+        assert_eq!(registry.drop(glass), None);
+        assert_eq!(registry.get(dirt).unwrap().hardness, 500.0);
+        assert!(registry.get(glass).unwrap().transparent);
+        assert!(registry.get(AIR).is_none());
+    }
+
+    #[test]
+    fn reports_error_lines() {
+        let text = "[stone]\ntexture = stone.png\ndrop = gravel\n";
+        let err = BlockRegistry::parse(text, "blocks.txt", Path::new("")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "blocks.txt:1: stone drops unknown block gravel"
+        );
+        let text = "[stone]\ntexture = stone.png\nhardness = soft\n";
+        let err = BlockRegistry::parse(text, "blocks.txt", Path::new("")).unwrap_err();
+        assert_eq!(err.to_string(), "blocks.txt:3: invalid hardness soft");
+    }
+
+    #[test]
+    fn rejects_duplicate_blocks() {
+        let text = "[stone]\ntexture = stone.png\n[stone]\ntexture = cobble.png\n";
+        let err = BlockRegistry::parse(text, "blocks.txt", Path::new("")).unwrap_err();
+        assert_eq!(err.to_string(), "blocks.txt:3: duplicate block stone");
+    }
+
+    #[test]
+    fn rejects_negative_and_non_finite_hardness() {
+        for value in ["-5", "NaN", "inf"] {
+            let text = format!("[stone]\ntexture = stone.png\nhardness = {value}\n");
+            let err = BlockRegistry::parse(&text, "blocks.txt", Path::new("")).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("blocks.txt:3: invalid hardness {value}")
+            );
+        }
+    }
+
+    #[test]
+    fn load_rejects_tiles_past_the_strip() {
+        let dir = std::env::temp_dir().join(format!("blocks_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Strip of two 16x16 tiles
+        image::RgbaImage::new(32, 16)
+            .save(dir.join("strip.png"))
+            .unwrap();
+        let path = dir.join("blocks.txt");
+        std::fs::write(
+            &path,
+            "[ore]\ntexture = strip.png:1\n\n[gem]\ntexture = strip.png:2\n",
+        )
+        .unwrap();
+        let err = BlockRegistry::load(path.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, Error::Parse { line: 4, .. }), "{err}");
+        assert!(err.to_string().contains("gem uses tile 2"), "{err}");
+        std::fs::write(&path, "[ore]\ntexture = strip.png:1\n").unwrap();
+        assert_eq!(
+            BlockRegistry::load(path.to_str().unwrap()).unwrap().len(),
+            1
+        );
+    }
+}
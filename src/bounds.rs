@@ -0,0 +1,123 @@
+use crate::{
+    draw::CLIP_PLANES,
+    types::{Mat4, Vec3, Vec4},
+};
+
+// Axis aligned box and the sphere around it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+impl Bounds {
+    pub fn new(min: [f32; 3], max: [f32; 3]) -> Self {
+        let (min, max) = (Vec3(min), Vec3(max));
+        let center = (min + max) * 0.5;
+        Bounds {
+            min: min.0,
+            max: max.0,
+            center: center.0,
+            radius: (max - center).length(),
+        }
+    }
+    // Smallest box holding all points, empty input gives a box around the origin
+    pub fn from_points<I: IntoIterator<Item = [f32; 3]>>(points: I) -> Self {
+        let mut points = points.into_iter().peekable();
+        if points.peek().is_none() {
+            return Bounds::default();
+        }
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in points {
+            for i in 0..3 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+        Bounds::new(min, max)
+    }
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds::from_points([self.min, self.max, other.min, other.max])
+    }
+    pub fn corners(&self) -> [[f32; 3]; 8] {
+        let (a, b) = (self.min, self.max);
+        std::array::from_fn(|i| {
+            [
+                if i & 1 == 0 { a[0] } else { b[0] },
+                if i & 2 == 0 { a[1] } else { b[1] },
+                if i & 4 == 0 { a[2] } else { b[2] },
+            ]
+        })
+    }
+    // Box around the transformed corners
+    pub fn transform(&self, m: &Mat4) -> Bounds {
+        Bounds::from_points(self.corners().map(|c| (m * Vec3(c)).0))
+    }
+}
+
+// Frustum planes in the space the matrix transforms from, a point is inside when
+// dot(plane, point) >= 0 for all of them
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+impl Frustum {
+    // With a model-view-projection matrix the planes end up in model space
+    pub fn new(mvp: &Mat4) -> Self {
+        let m = mvp.transpose();
+        Frustum {
+            planes: CLIP_PLANES.map(|plane| {
+                let p = &m * plane;
+                // Normalized, so the sphere test can compare distances
+                let len = p.xyz().length();
+                if len > 0.0 {
+                    p * (1.0 / len)
+                } else {
+                    p
+                }
+            }),
+        }
+    }
+    // False only when the bounds are surely outside, the sphere rejects first and the box
+    // catches what the sphere misses
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        let center = Vec4::point(bounds.center);
+        if self
+            .planes
+            .iter()
+            .any(|plane| plane.dot(&center) < -bounds.radius)
+        {
+            return false;
+        }
+        // Box is out when even its corner furthest along the plane normal is behind it
+        !self.planes.iter().any(|plane| {
+            let corner = Vec4::point(std::array::from_fn(|i| {
+                if plane[i] >= 0.0 {
+                    bounds.max[i]
+                } else {
+                    bounds.min[i]
+                }
+            }));
+            plane.dot(&corner) < 0.0
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+
+    #[test]
+    fn frustum_rejects_bounds_outside() {
+        // Camera at the origin looking down -z
+        let frustum = Frustum::new(&Camera::new([0.0; 3], [0.0; 3]).view_proj(64, 64));
+        let at = |z: f32| Bounds::new([-0.5, -0.5, z - 0.5], [0.5, 0.5, z + 0.5]);
+        assert!(frustum.intersects(&at(-5.0)));
+        assert!(!frustum.intersects(&at(5.0)));
+        assert!(!frustum.intersects(&at(-1000.0)));
+        // Off to the side, but straddling the near plane
+        assert!(frustum.intersects(&Bounds::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0])));
+        assert!(!frustum.intersects(&Bounds::new([50.0, -0.5, -5.5], [51.0, 0.5, -4.5])));
+    }
+}
@@ -0,0 +1,36 @@
+use crate::types::Mat4;
+
+// Point of view used to build the view-projection matrix for a frame
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub pos: [f32; 3],
+    pub rot: [f32; 3],
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+}
+impl Camera {
+    pub fn new(pos: [f32; 3], rot: [f32; 3]) -> Self {
+        Camera {
+            pos,
+            rot,
+            fov_y: 60.0f32.to_radians(),
+            near: 0.1,
+            far: 300.0,
+        }
+    }
+    // Objects are placed at their negated positions and the view adds pos, so the eye
+    // ends up at -pos in world space
+    pub fn eye(&self) -> [f32; 3] {
+        [-self.pos[0], -self.pos[1], -self.pos[2]]
+    }
+    pub fn view(&self) -> Mat4 {
+        Mat4::identity().rotate(self.rot) * Mat4::trans(self.pos)
+    }
+    pub fn projection(&self, aspect_ratio: f32) -> Mat4 {
+        Mat4::projection(self.fov_y, aspect_ratio, self.near, self.far)
+    }
+    pub fn view_proj(&self, width: usize, height: usize) -> Mat4 {
+        self.projection(width as f32 / height as f32) * self.view()
+    }
+}
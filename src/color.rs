@@ -0,0 +1,162 @@
+use crate::{draw::rgb_to_u32, window::Framebuffer};
+use rayon::prelude::*;
+use std::sync::LazyLock;
+
+// Linear value of every 8-bit sRGB level
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> =
+    LazyLock::new(|| std::array::from_fn(|i| srgb_to_linear(i as f32 / 255.0)));
+// 8-bit sRGB level of linear values in steps of 1/4095, fine enough for the dark end
+const ENCODE_STEPS: usize = 4096;
+static LINEAR_TO_SRGB: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    (0..ENCODE_STEPS)
+        .map(|i| (linear_to_srgb(i as f32 / (ENCODE_STEPS - 1) as f32) * 255.0).round() as u8)
+        .collect()
+});
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+// Table lookup of srgb_to_linear for 8-bit levels
+#[inline(always)]
+pub fn decode_srgb8(c: u8) -> f32 {
+    SRGB_TO_LINEAR[c as usize]
+}
+// Clamps to 0..1 and encodes into 8-bit sRGB
+#[inline(always)]
+pub fn encode_srgb8(c: f32) -> u8 {
+    LINEAR_TO_SRGB[(c.clamp(0.0, 1.0) * (ENCODE_STEPS - 1) as f32 + 0.5) as usize]
+}
+
+// Maps HDR color into 0..1 before encoding
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapping {
+    // Cuts everything above 1
+    Clamp,
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve
+    #[default]
+    Aces,
+}
+impl ToneMapping {
+    pub fn apply(self, c: f32) -> f32 {
+        match self {
+            ToneMapping::Clamp => c,
+            ToneMapping::Reinhard => c / (1.0 + c),
+            ToneMapping::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+        }
+    }
+}
+
+// Linear HDR color the scene is lit into, resolved into the framebuffer once per frame
+#[derive(Clone, Debug)]
+pub struct ColorBuffer {
+    data: Vec<[f32; 3]>,
+    width: usize,
+    height: usize,
+}
+impl ColorBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        ColorBuffer {
+            data: vec![[0.0; 3]; width * height],
+            width,
+            height,
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.data = vec![[0.0; 3]; width * height];
+    }
+    pub fn get(&self, x: usize, y: usize) -> [f32; 3] {
+        self.data[x + y * self.width]
+    }
+    pub fn set(&mut self, x: usize, y: usize, col: [f32; 3]) {
+        self.data[x + y * self.width] = col;
+    }
+    pub fn clear(&mut self, col: [f32; 3]) {
+        self.data.fill(col);
+    }
+    pub fn data(&self) -> &[[f32; 3]] {
+        &self.data
+    }
+    pub fn data_mut(&mut self) -> &mut [[f32; 3]] {
+        &mut self.data
+    }
+    // Tone maps and sRGB encodes the whole buffer into the framebuffer of the same size
+    pub fn resolve(&self, fb: &mut Framebuffer, tone_mapping: ToneMapping) {
+        fb.data_mut()
+            .par_iter_mut()
+            .zip(self.data.par_iter())
+            .for_each(|(px, col)| {
+                let [r, g, b] = col.map(|c| encode_srgb8(tone_mapping.apply(c.max(0.0))));
+                *px = rgb_to_u32(r, g, b);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_mapping_curves() {
+        for mapping in [ToneMapping::Clamp, ToneMapping::Reinhard, ToneMapping::Aces] {
+            assert_eq!(mapping.apply(0.0), 0.0);
+            let curve: Vec<f32> = (0..64).map(|i| mapping.apply(i as f32 * 0.25)).collect();
+            assert!(curve.windows(2).all(|w| w[0] < w[1]), "{mapping:?}");
+        }
+        // Clamping is left to the encoding
+        assert_eq!(ToneMapping::Clamp.apply(4.0), 4.0);
+        assert_eq!(ToneMapping::Reinhard.apply(1.0), 0.5);
+        assert_eq!(ToneMapping::Reinhard.apply(3.0), 0.75);
+        assert!(ToneMapping::Reinhard.apply(1000.0) < 1.0);
+        let aces = ToneMapping::Aces;
+        assert!((aces.apply(1.0) - 0.8038).abs() < 1e-3);
+        // Bright values saturate to white instead of growing further
+        assert!(aces.apply(16.0) > 1.0 && aces.apply(1000.0) < 1.04);
+    }
+
+    #[test]
+    fn resolve_tone_maps_and_encodes() {
+        let mut buffer = ColorBuffer::new(3, 1);
+        buffer.set(0, 0, [0.0, 1.0, 4.0]);
+        buffer.set(1, 0, [0.5, decode_srgb8(128), -1.0]);
+        buffer.set(2, 0, [3.0, 1.0, 0.0]);
+        let mut fb = Framebuffer::new(3, 1);
+        buffer.resolve(&mut fb, ToneMapping::Clamp);
+        assert_eq!(fb.get_pixel(0, 0), rgb_to_u32(0, 255, 255));
+        // Half the light is brighter than half the encoded value, negatives are black
+        assert_eq!(fb.get_pixel(1, 0), rgb_to_u32(188, 128, 0));
+        buffer.resolve(&mut fb, ToneMapping::Reinhard);
+        // 3 / (1 + 3) and 1 / (1 + 1)
+        let (r, g) = (encode_srgb8(0.75), encode_srgb8(0.5));
+        assert_eq!(fb.get_pixel(2, 0), rgb_to_u32(r, g, 0));
+        assert_eq!(g, 188);
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for i in 0..=255u8 {
+            assert_eq!(encode_srgb8(decode_srgb8(i)), i);
+        }
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        assert!((linear_to_srgb(srgb_to_linear(0.3)) - 0.3).abs() < 1e-5);
+    }
+}
@@ -15,6 +15,11 @@ pub struct Controls {
     pub rot: [f32; 3],
     pub action: Action,
 }
+impl Default for Controls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Controls {
     pub fn new() -> Self {
         Controls {
@@ -0,0 +1,139 @@
+// Comparison between incoming and stored depth, fragment passes when it returns true
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthFunc {
+    Less,
+    LessEqual,
+    // For reverse-Z, where near maps to 1 and far to 0
+    Greater,
+    Always,
+}
+impl DepthFunc {
+    #[inline(always)]
+    pub fn test(self, z: f32, stored: f32) -> bool {
+        match self {
+            DepthFunc::Less => z < stored,
+            DepthFunc::LessEqual => z <= stored,
+            DepthFunc::Greater => z > stored,
+            DepthFunc::Always => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DepthBuffer {
+    data: Vec<f32>,
+    width: usize,
+    height: usize,
+    pub func: DepthFunc,
+    // When false the depth test still runs, but passing fragments don't update the buffer
+    pub write: bool,
+    pub clear_value: f32,
+}
+impl DepthBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        DepthBuffer {
+            data: vec![1.0; width * height],
+            width,
+            height,
+            func: DepthFunc::Less,
+            write: true,
+            clear_value: 1.0,
+        }
+    }
+    // Greater test cleared to 0, to be used with Mat4::projection_reverse_z
+    pub fn reverse_z(width: usize, height: usize) -> Self {
+        let mut depth = DepthBuffer::new(width, height).with_func(DepthFunc::Greater);
+        depth.clear_value = 0.0;
+        depth.clear();
+        depth
+    }
+    pub fn with_func(mut self, func: DepthFunc) -> Self {
+        self.func = func;
+        self
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    // Changes size and keeps the settings, content is reset to the clear value
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.data = vec![self.clear_value; width * height];
+    }
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.data[x + y * self.width]
+    }
+    pub fn set(&mut self, x: usize, y: usize, val: f32) {
+        self.data[x + y * self.width] = val;
+    }
+    // Runs depth test at the pixel and stores z if it passes and writes are enabled
+    pub fn test_and_set(&mut self, x: usize, y: usize, z: f32) -> bool {
+        let i = x + y * self.width;
+        if !self.func.test(z, self.data[i]) {
+            return false;
+        }
+        if self.write {
+            self.data[i] = z;
+        }
+        true
+    }
+    pub fn clear(&mut self) {
+        let val = self.clear_value;
+        self.data.fill(val);
+    }
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+    pub fn data_mut(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_funcs_compare() {
+        let cases = [
+            (DepthFunc::Less, [true, false, false]),
+            (DepthFunc::LessEqual, [true, true, false]),
+            (DepthFunc::Greater, [false, false, true]),
+            (DepthFunc::Always, [true, true, true]),
+        ];
+        // Nearer, equal and farther than the stored 0.5
+        for (func, expected) in cases {
+            let passed = [0.25, 0.5, 0.75].map(|z| func.test(z, 0.5));
+            assert_eq!(passed, expected, "{func:?}");
+        }
+    }
+
+    #[test]
+    fn write_mask_keeps_stored_depth() {
+        let mut depth = DepthBuffer::new(2, 1);
+        assert!(depth.test_and_set(0, 0, 0.5));
+        assert_eq!(depth.get(0, 0), 0.5);
+        assert!(!depth.test_and_set(0, 0, 0.75));
+        assert_eq!(depth.get(0, 0), 0.5);
+        depth.write = false;
+        // Still tested, but not stored
+        assert!(depth.test_and_set(0, 0, 0.25));
+        assert_eq!(depth.get(0, 0), 0.5);
+        assert!(!depth.test_and_set(0, 0, 0.75));
+    }
+
+    #[test]
+    fn reverse_z_clears_to_far() {
+        let mut depth = DepthBuffer::reverse_z(2, 2);
+        assert!(depth.data().iter().all(|&z| z == 0.0));
+        assert!(depth.test_and_set(1, 1, 0.25));
+        assert!(!depth.test_and_set(1, 1, 0.1));
+        depth.clear();
+        assert_eq!(depth.get(1, 1), 0.0);
+        depth.resize(3, 1);
+        assert_eq!(depth.data(), &[0.0; 3]);
+    }
+}
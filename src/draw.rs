@@ -1,64 +1,79 @@
 use crate::{
-    model::{Material, Model, Vertex},
-    types::Matrix,
-    window::Framebuffer,
-};
-use rayon::prelude::*; // For parallelizatio
-use std::sync::{Arc, Mutex};
-use std::{
-    sync::atomic::{AtomicU32, Ordering},
-    u32,
+    atlas::UvRect,
+    bounds::Frustum,
+    color::{decode_srgb8, ColorBuffer},
+    depth::{DepthBuffer, DepthFunc},
+    light::Lighting,
+    model::{AlphaMode, Material, Model, Shading, Vertex},
+    texture::{SampledTexture, TexCoord},
+    types::{Mat3, Mat4, Vec3, Vec4},
 };
+use rayon::prelude::*;
+
+// Rows of the screen shaded by one task of the parallel rasterizer
+pub const TILE_HEIGHT: usize = 16;
 pub fn rgb_to_u32(r: u8, g: u8, b: u8) -> u32 {
     let (r, g, b) = (r as u32, g as u32, b as u32);
     (r << 16) | (g << 8) | b
 }
-fn blend_pixel(dst: u32, src: u32, alpha: f32) -> u32 {
-    let src_r = ((src >> 16) & 0xFF) as f32;
-    let src_g = ((src >> 8) & 0xFF) as f32;
-    let src_b = (src & 0xFF) as f32;
-
-    let dst_r = ((dst >> 16) & 0xFF) as f32;
-    let dst_g = ((dst >> 8) & 0xFF) as f32;
-    let dst_b = (dst & 0xFF) as f32;
-
-    let r = (src_r * alpha + dst_r * (1.0 - alpha)) as u32;
-    let g = (src_g * alpha + dst_g * (1.0 - alpha)) as u32;
-    let b = (src_b * alpha + dst_b * (1.0 - alpha)) as u32;
-
-    (r << 16) | (g << 8) | b
+// Multiplies normal by the inverse-transpose of the model matrix
+pub fn transform_normal(normal: [f32; 3], invmod: &Mat3) -> [f32; 3] {
+    (invmod * Vec3(normal)).0
 }
-pub fn transform_normal(normal: [f32; 3], invmod: &Matrix) -> [f32; 3] {
-    // Convert normal to homogeneous coordinates (4D)
-    let normal_homogeneous = [normal[0], normal[1], normal[2], 0.0]; // The fourth component is 0 for normals
-
-    // Multiply by the inverse-transpose matrix (4x4)
-
-    [
-        normal_homogeneous[0] * invmod[0][0]
-            + normal_homogeneous[1] * invmod[1][0]
-            + normal_homogeneous[2] * invmod[2][0]
-            + normal_homogeneous[3] * invmod[3][0],
-        normal_homogeneous[0] * invmod[0][1]
-            + normal_homogeneous[1] * invmod[1][1]
-            + normal_homogeneous[2] * invmod[2][1]
-            + normal_homogeneous[3] * invmod[3][1],
-        normal_homogeneous[0] * invmod[0][2]
-            + normal_homogeneous[1] * invmod[1][2]
-            + normal_homogeneous[2] * invmod[2][2]
-            + normal_homogeneous[3] * invmod[3][2],
-    ]
+// Transforms point into homogeneous clip space
+pub fn to_clip(p: &[f32; 3], mvp: &Mat4) -> Vec4 {
+    mvp * Vec4::point(*p)
+}
+// Returns normalized device coordinates and reciprocal of w used for perspective correction
+pub fn perspective_divide(clip: &Vec4) -> ([f32; 3], f32) {
+    let rec = 1.0 / clip[3];
+    ([clip[0] * rec, clip[1] * rec, clip[2] * rec], rec)
+}
+// Perspective-correct interpolation of a vertex attribute with per-vertex weights
+#[inline(always)]
+fn interpolate<const N: usize>(a: &[f32; N], b: &[f32; N], c: &[f32; N], w: &[f32; 3]) -> [f32; N] {
+    std::array::from_fn(|i| a[i] * w[0] + b[i] * w[1] + c[i] * w[2])
+}
+// Which facing of triangles is skipped. Front faces are counter-clockwise as in glTF.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CullMode {
+    #[default]
+    Back,
+    Front,
+    None,
+}
+// Per-draw state shared by every triangle of a model
+pub struct Uniforms<'a> {
+    pub mvp: Mat4,
+    pub model: Mat4,
+    // Inverse-transpose of the model matrix, for normals
+    pub normal: Mat3,
+    pub lighting: &'a Lighting,
+    // Ignored by double sided materials
+    pub cull: CullMode,
+}
+impl<'a> Uniforms<'a> {
+    pub fn new(view_proj: &Mat4, model: Mat4, lighting: &'a Lighting) -> Self {
+        Uniforms {
+            mvp: *view_proj * model,
+            normal: model.normal_matrix(),
+            model,
+            lighting,
+            cull: CullMode::Back,
+        }
+    }
+    pub fn with_cull(mut self, cull: CullMode) -> Self {
+        self.cull = cull;
+        self
+    }
 }
 // This shit
-pub fn project(p: &[f32; 3], mvp: &Matrix) -> ([f32; 3], f32) {
-    let mut p4: Matrix = vec![p.to_vec()].into();
-    p4[0].push(1.0);
-    let proj_pos = &(mvp * &p4)[0];
-    let rec = 1.0 / proj_pos[3].abs();
-    let rec_pos = proj_pos.iter().map(|a| a * rec).collect::<Vec<_>>();
-    ([rec_pos[0], rec_pos[1], rec_pos[2]], rec)
-}
-pub fn clip_to_screen(clip: [f32; 2], screen_size: &Vec<f32>) -> [f32; 2] {
+pub fn project(p: &[f32; 3], mvp: &Mat4) -> ([f32; 3], f32) {
+    let clip = to_clip(p, mvp);
+    let rec = 1.0 / clip[3].abs();
+    ([clip[0] * rec, clip[1] * rec, clip[2] * rec], rec)
+}
+pub fn clip_to_screen(clip: [f32; 2], screen_size: &[f32]) -> [f32; 2] {
     [
         (clip[0] * 0.5 + 0.5) * screen_size[0],
         (clip[1] * 0.5 + 0.5) * screen_size[1],
@@ -70,11 +85,11 @@ pub fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
     ((p[0] - a[0]) * (b[1] - a[1])) - ((p[1] - a[1]) * (b[0] - a[0]))
 }
 pub fn draw_line(
-    fb: &mut Framebuffer,
-    depth_buffer: &mut Framebuffer,
+    fb: &mut ColorBuffer,
+    depth_buffer: &mut DepthBuffer,
     v0: &[f32; 3],
     v1: &[f32; 3],
-    mvp: &Matrix,
+    mvp: &Mat4,
     col: &[u8; 3],
 ) {
     // println!("VLine from {:?} to {:?}", v0, v1);
@@ -105,15 +120,13 @@ pub fn draw_line(
     // println!("Line from {}, {} to {},{}", x, y, xm, ym);
     while x != xm || y != ym {
         if x >= 0 && x < screen_size[0] as i32 && y >= 0 && y < screen_size[1] as i32 {
-            let z = depth_buffer.get_pixel_f32(x as usize, y as usize);
             let xd = xm - x;
             let yd = ym - y;
             let ad = (((xd * xd) + (yd * yd)) as f32).sqrt();
             let t = ad / dist;
             let d = v0_clip.0[2] + (dd * t * sd);
-            if d < z {
-                depth_buffer.set_pixel_f32(x as usize, y as usize, d);
-                fb.set_pixel(x as usize, y as usize, rgb_to_u32(col[0], col[1], col[2]));
+            if depth_buffer.test_and_set(x as usize, y as usize, d) {
+                fb.set(x as usize, y as usize, col.map(decode_srgb8));
             }
         }
         let e2 = err * 2.0;
@@ -132,34 +145,359 @@ pub fn draw_line(
         }
     }
 }
+// Vertex in homogeneous clip space together with the attributes interpolated across the triangle
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClipVertex {
+    pub position: Vec4,
+    // World space position, normal and tangent for lighting
+    pub world_pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    pub tex_coord: [f32; 2],
+    // Same for the whole triangle, so it's not interpolated
+    pub tile: Option<UvRect>,
+}
+impl ClipVertex {
+    pub fn new(v: &Vertex, uniforms: &Uniforms) -> Self {
+        ClipVertex {
+            position: to_clip(&v.position, &uniforms.mvp),
+            world_pos: (&uniforms.model * Vec3(v.position)).0,
+            normal: transform_normal(v.normal, &uniforms.normal),
+            tangent: {
+                let [x, y, z, w] = v.tangent;
+                let t = (&uniforms.model * Vec4::dir([x, y, z])).xyz();
+                [t[0], t[1], t[2], w]
+            },
+            tex_coord: v.tex_coord,
+            tile: v.tile,
+        }
+    }
+    // Attributes are linear in clip space, so plain lerp is correct before the perspective divide
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        fn l<const N: usize>(a: &[f32; N], b: &[f32; N], t: f32) -> [f32; N] {
+            std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+        }
+        ClipVertex {
+            position: self.position + (other.position - self.position) * t,
+            world_pos: l(&self.world_pos, &other.world_pos, t),
+            normal: l(&self.normal, &other.normal, t),
+            tangent: l(&self.tangent, &other.tangent, t),
+            tex_coord: l(&self.tex_coord, &other.tex_coord, t),
+            tile: self.tile,
+        }
+    }
+}
+// Frustum planes in clip space, a point is inside when dot(plane, position) >= 0.
+// Depth is kept in 0..1 (same as the depth test), so the near plane is z >= 0 and not z >= -w.
+pub(crate) const CLIP_PLANES: [Vec4; 6] = [
+    Vec4([0.0, 0.0, 1.0, 0.0]),  // Near
+    Vec4([0.0, 0.0, -1.0, 1.0]), // Far
+    Vec4([1.0, 0.0, 0.0, 1.0]),  // Left
+    Vec4([-1.0, 0.0, 0.0, 1.0]), // Right
+    Vec4([0.0, 1.0, 0.0, 1.0]),  // Bottom
+    Vec4([0.0, -1.0, 0.0, 1.0]), // Top
+];
+// Every plane can add at most one vertex to a triangle
+const MAX_CLIP_VERTS: usize = 3 + CLIP_PLANES.len();
+
+// Convex polygon left after clipping, kept on the stack
+#[derive(Clone, Copy)]
+pub struct ClipPolygon {
+    verts: [ClipVertex; MAX_CLIP_VERTS],
+    len: usize,
+}
+impl ClipPolygon {
+    fn new() -> Self {
+        ClipPolygon {
+            verts: [ClipVertex::default(); MAX_CLIP_VERTS],
+            len: 0,
+        }
+    }
+    fn push(&mut self, v: ClipVertex) {
+        self.verts[self.len] = v;
+        self.len += 1;
+    }
+    pub fn vertices(&self) -> &[ClipVertex] {
+        &self.verts[..self.len]
+    }
+    // Splits the polygon into a fan of triangles with the original winding
+    pub fn triangles(self) -> impl Iterator<Item = [ClipVertex; 3]> {
+        (1..self.len.saturating_sub(1))
+            .map(move |i| [self.verts[0], self.verts[i], self.verts[i + 1]])
+    }
+}
+// Sutherland-Hodgman clipping of a triangle against all six frustum planes
+pub fn clip_triangle(v0: ClipVertex, v1: ClipVertex, v2: ClipVertex) -> ClipPolygon {
+    let mut poly = ClipPolygon::new();
+    poly.push(v0);
+    poly.push(v1);
+    poly.push(v2);
+
+    for plane in CLIP_PLANES.iter() {
+        // Skip the work when the whole polygon is on the inside
+        if poly
+            .vertices()
+            .iter()
+            .all(|v| plane.dot(&v.position) >= 0.0)
+        {
+            continue;
+        }
+        let mut out = ClipPolygon::new();
+        for i in 0..poly.len {
+            let cur = poly.verts[i];
+            let next = poly.verts[(i + 1) % poly.len];
+            let dc = plane.dot(&cur.position);
+            let dn = plane.dot(&next.position);
+            if dc >= 0.0 {
+                out.push(cur);
+            }
+            if (dc >= 0.0) != (dn >= 0.0) {
+                out.push(cur.lerp(&next, dc / (dc - dn)));
+            }
+        }
+        poly = out;
+        if poly.len < 3 {
+            poly.len = 0;
+            break;
+        }
+    }
+    poly
+}
+// Triangle after clipping and projection, set up once and then rasterized into any tile
+#[derive(Clone, Copy)]
+pub struct ScreenTriangle {
+    verts: [ClipVertex; 3],
+    screen: [[f32; 2]; 3],
+    // NDC depth and reciprocal of w for every vertex
+    clip: [([f32; 3], f32); 3],
+    // Pixel bounds as xs, ys, xl, yl (upper ones exclusive)
+    bounds: [usize; 4],
+    // Signed area from edge, negative for front faces
+    area: f32,
+}
+impl ScreenTriangle {
+    pub fn new(tri: &[ClipVertex; 3], width: usize, height: usize) -> Self {
+        let clip = [
+            perspective_divide(&tri[0].position),
+            perspective_divide(&tri[1].position),
+            perspective_divide(&tri[2].position),
+        ];
+        let screen_size = [width as f32, height as f32];
+        let a = clip_to_screen([clip[0].0[0], clip[0].0[1]], &screen_size);
+        let b = clip_to_screen([clip[1].0[0], clip[1].0[1]], &screen_size);
+        let c = clip_to_screen([clip[2].0[0], clip[2].0[1]], &screen_size);
+
+        let xs = (a[0].min(b[0]).min(c[0]).max(0.0)).floor() as usize;
+        let ys = (a[1].min(b[1]).min(c[1]).max(0.0)).floor() as usize;
+        let xl = (a[0].max(b[0]).max(c[0]).min(screen_size[0] - 1.0)).ceil() as usize;
+        let yl = (a[1].max(b[1]).max(c[1]).min(screen_size[1] - 1.0)).ceil() as usize;
+
+        ScreenTriangle {
+            verts: *tri,
+            screen: [a, b, c],
+            clip,
+            bounds: [xs, ys, xl, yl],
+            area: edge(a, b, c),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.bounds[0] >= self.bounds[2] || self.bounds[1] >= self.bounds[3] || self.area == 0.0
+    }
+    pub fn is_back_facing(&self) -> bool {
+        self.area > 0.0
+    }
+    // Whether the triangle is skipped, double sided materials show both sides
+    pub fn is_culled(&self, cull: CullMode, mat: &Material) -> bool {
+        match cull {
+            _ if mat.double_sided => false,
+            CullMode::Back => self.is_back_facing(),
+            CullMode::Front => !self.is_back_facing(),
+            CullMode::None => false,
+        }
+    }
+}
+// Horizontal band of the color and depth buffers owned by one rasterizer task. Without color
+// only depth is written, as for shadow maps.
+pub struct Tile<'a> {
+    color: Option<&'a mut [[f32; 3]]>,
+    depth: &'a mut [f32],
+    depth_func: DepthFunc,
+    depth_write: bool,
+    width: usize,
+    // First and past the last row covered by the tile
+    y0: usize,
+    y1: usize,
+}
+impl<'a> Tile<'a> {
+    pub fn new(
+        color: Option<&'a mut [[f32; 3]]>,
+        depth: &'a mut [f32],
+        depth_func: DepthFunc,
+        depth_write: bool,
+        width: usize,
+        y0: usize,
+    ) -> Self {
+        let y1 = y0 + depth.len() / width;
+        Tile {
+            color,
+            depth,
+            depth_func,
+            depth_write,
+            width,
+            y0,
+            y1,
+        }
+    }
+    // Whole screen as a single tile, used by the serial path
+    pub fn full(fb: &'a mut ColorBuffer, depth_buffer: &'a mut DepthBuffer) -> Self {
+        let width = fb.width();
+        let (func, write) = (depth_buffer.func, depth_buffer.write);
+        Tile::new(
+            Some(fb.data_mut()),
+            depth_buffer.data_mut(),
+            func,
+            write,
+            width,
+            0,
+        )
+    }
+    fn index(&self, x: usize, y: usize) -> usize {
+        x + (y - self.y0) * self.width
+    }
+}
 pub fn draw_triangle(
-    fb: &mut Framebuffer,
-    depth_buffer: &mut Framebuffer,
+    fb: &mut ColorBuffer,
+    depth_buffer: &mut DepthBuffer,
     v0: &Vertex,
     v1: &Vertex,
     v2: &Vertex,
-    mvp: &Matrix,
-    invmod: &Matrix,
+    uniforms: &Uniforms,
     mat: &Material,
 ) {
-    let v0_clip = project(&v0.position, mvp);
-    let v1_clip = project(&v1.position, mvp);
-    let v2_clip = project(&v2.position, mvp);
-    if v0_clip.1 < 0.0 && v1_clip.1 < 0.0 && v2_clip.1 < 0.0 {
-        return; // Discard triangle
+    let (width, height) = (fb.width(), fb.height());
+    let poly = clip_triangle(
+        ClipVertex::new(v0, uniforms),
+        ClipVertex::new(v1, uniforms),
+        ClipVertex::new(v2, uniforms),
+    );
+    let mut tile = Tile::full(fb, depth_buffer);
+    for tri in poly.triangles() {
+        let tri = ScreenTriangle::new(&tri, width, height);
+        if tri.is_empty() || tri.is_culled(uniforms.cull, mat) {
+            continue;
+        }
+        rasterize_triangle(&mut tile, &tri, uniforms.lighting, mat);
     }
-
-    let screen_size = vec![fb.width() as f32, fb.height() as f32];
-    let a = clip_to_screen([v0_clip.0[0], v0_clip.0[1]], &screen_size);
-    let b = clip_to_screen([v1_clip.0[0], v1_clip.0[1]], &screen_size);
-    let c = clip_to_screen([v2_clip.0[0], v2_clip.0[1]], &screen_size);
-
-    // println!("{:?}\n{:?}\n{:?}\n", a, b, c);
-
-    let xs = (a[0].min(b[0]).min(c[0]).max(0.0)).floor() as usize;
-    let ys = (a[1].min(b[1]).min(c[1]).max(0.0)).floor() as usize;
-    let xl = (a[0].max(b[0]).max(c[0]).min(screen_size[0] - 1.0)).ceil() as usize;
-    let yl = (a[1].max(b[1]).max(c[1]).min(screen_size[1] - 1.0)).ceil() as usize;
+}
+// Normal from a tangent space normal map, rotated into world space by the TBN basis
+fn perturb_normal(
+    normal: &Vec3,
+    tangent: &[f32; 4],
+    normal_texture: &SampledTexture,
+    scale: f32,
+    tex: &TexCoord,
+) -> Vec3 {
+    let text = normal_texture.sample(tex);
+    let x = (text[0] * 2.0 - 1.0) * scale;
+    let y = (text[1] * 2.0 - 1.0) * scale;
+    let z = text[2] * 2.0 - 1.0;
+    let t = Vec3([tangent[0], tangent[1], tangent[2]]);
+    // Interpolation leaves the tangent slightly off perpendicular
+    let t = (t - *normal * normal.dot(&t)).normalize();
+    let sign = if tangent[3] < 0.0 { -1.0 } else { 1.0 };
+    let b = normal.cross(&t) * sign;
+    (t * x + b * y + *normal * z).normalize()
+}
+// Alpha of the base color, what the alpha test compares against the cutoff
+fn coverage(mat: &Material, tex: &TexCoord) -> f32 {
+    match &mat.base_color_texture {
+        Some(texture) => mat.base_col[3] * texture.sample(tex)[3],
+        None => mat.base_col[3],
+    }
+}
+// Lit color and alpha of the surface at one pixel
+fn shade(
+    lighting: &Lighting,
+    mat: &Material,
+    pos: &Vec3,
+    normal: &Vec3,
+    tangent: &[f32; 4],
+    tex: &TexCoord,
+) -> [f32; 4] {
+    let normal = match &mat.normal_texture {
+        Some(normal_texture) => {
+            perturb_normal(normal, tangent, normal_texture, mat.normal_scale, tex)
+        }
+        None => *normal,
+    };
+    let normal = &normal;
+    let mut base_color = mat.base_col;
+    if let Some(base_color_texture) = &mat.base_color_texture {
+        let text = base_color_texture.sample(tex);
+        base_color = [
+            base_color[0] * text[0],
+            base_color[1] * text[1],
+            base_color[2] * text[2],
+            base_color[3] * text[3],
+        ];
+    };
+    let albedo = [base_color[0], base_color[1], base_color[2]];
+    let occlusion = match &mat.occlusion_texture {
+        Some(texture) => 1.0 + mat.occlusion_strength * (texture.sample(tex)[0] - 1.0),
+        None => 1.0,
+    };
+    let lit = match mat.shading {
+        Shading::BlinnPhong => {
+            lighting.shade(pos, normal, albedo, mat.specular, mat.shininess, occlusion)
+        }
+        Shading::MetallicRoughness => {
+            let (mut metallic, mut roughness) = (mat.metallic, mat.roughness);
+            if let Some(texture) = &mat.metallic_roughness_texture {
+                let text = texture.sample(tex);
+                roughness *= text[1];
+                metallic *= text[2];
+            }
+            lighting.shade_pbr(pos, normal, albedo, metallic, roughness, occlusion)
+        }
+    };
+    let mut emissive = mat.emissive;
+    if let Some(texture) = &mat.emissive_texture {
+        let text = texture.sample(tex);
+        emissive = [
+            emissive[0] * text[0],
+            emissive[1] * text[1],
+            emissive[2] * text[2],
+        ];
+    }
+    [
+        lit[0] + emissive[0],
+        lit[1] + emissive[1],
+        lit[2] + emissive[2],
+        base_color[3],
+    ]
+}
+// Draws the part of already clipped triangle that falls into the tile
+fn rasterize_triangle(tile: &mut Tile, tri: &ScreenTriangle, lighting: &Lighting, mat: &Material) {
+    let [v0, v1, v2] = &tri.verts;
+    let [v0_clip, v1_clip, v2_clip] = tri.clip;
+    let [a, b, c] = tri.screen;
+    let [xs, ys, xl, yl] = tri.bounds;
+    let ys = ys.max(tile.y0);
+    let yl = yl.min(tile.y1);
+    // Change of the edge values one pixel right and one pixel down
+    let step_x = [c[1] - b[1], a[1] - c[1], b[1] - a[1]];
+    let step_y = [b[0] - c[0], c[0] - a[0], a[0] - b[0]];
+    // Perspective-correct texture coordinate at the given edge values
+    let uv_at = |e: [f32; 3]| {
+        let w = [e[0] * v0_clip.1, e[1] * v1_clip.1, e[2] * v2_clip.1];
+        let sum = w[0] + w[1] + w[2];
+        interpolate(
+            &v0.tex_coord,
+            &v1.tex_coord,
+            &v2.tex_coord,
+            &w.map(|w| w / sum),
+        )
+    };
 
     for x in xs..xl {
         for y in ys..yl {
@@ -168,117 +506,424 @@ pub fn draw_triangle(
             let a1 = edge(c, a, p);
             let a2 = edge(a, b, p);
 
-            let inside = a0 < 0.0 && a1 < 0.0 && a2 < 0.0;
+            // Edges have the sign of the area inside the triangle
+            let inside = if tri.is_back_facing() {
+                a0 > 0.0 && a1 > 0.0 && a2 > 0.0
+            } else {
+                a0 < 0.0 && a1 < 0.0 && a2 < 0.0
+            };
             if inside {
-                let area_rep = 1.0 / edge(a, b, c);
+                let area_rep = 1.0 / tri.area;
                 let bary0 = a0 * area_rep;
                 let bary1 = a1 * area_rep;
                 let bary2 = a2 * area_rep;
                 let correction = 1.0 / (bary0 * v0_clip.1 + bary1 * v1_clip.1 + bary2 * v2_clip.1);
 
-                //println!("{:?}", v1_clip.0);
                 let z = (v0_clip.0[2] * bary0) + (v1_clip.0[2] * bary1) + (v2_clip.0[2] * bary2);
                 if !(0.0..=1.0).contains(&z) {
                     continue;
                 }
-                let d = depth_buffer.get_pixel_f32(x, y);
-
-                if z < d {
-                    // println!("{}", z);
-                    depth_buffer.set_pixel_f32(x, y, z);
-                    // Directly interpolate normals
-                    let normal = [
-                        (v0.normal[0] * v0_clip.1 * bary0
-                            + v1.normal[0] * v1_clip.1 * bary1
-                            + v2.normal[0] * v2_clip.1 * bary2)
-                            * correction,
-                        (v0.normal[1] * v0_clip.1 * bary0
-                            + v1.normal[1] * v1_clip.1 * bary1
-                            + v2.normal[1] * v2_clip.1 * bary2)
-                            * correction,
-                        (v0.normal[2] * v0_clip.1 * bary0
-                            + v1.normal[2] * v1_clip.1 * bary1
-                            + v2.normal[2] * v2_clip.1 * bary2)
-                            * correction,
-                    ];
-
-                    // Normalize the interpolated normal
-                    let len = (normal[0].powi(2) + normal[1].powi(2) + normal[2].powi(2)).sqrt();
-                    let normal = [normal[0] / len, normal[1] / len, normal[2] / len];
-
-                    let normal = transform_normal(normal, &invmod);
+                let i = tile.index(x, y);
 
-                    let tex = [
-                        (v0.tex_coord[0] * v0_clip.1 * bary0
-                            + v1.tex_coord[0] * v1_clip.1 * bary1
-                            + v2.tex_coord[0] * v2_clip.1 * bary2)
-                            * correction,
-                        (v0.tex_coord[1] * v0_clip.1 * bary0
-                            + v1.tex_coord[1] * v1_clip.1 * bary1
-                            + v2.tex_coord[1] * v2_clip.1 * bary2)
-                            * correction,
-                    ];
-
-                    // Scale the normal to color space
-                    let norm_col = [
-                        ((normal[0] * 0.5 + 0.5) * 255.99) as u8,
-                        ((normal[1] * 0.5 + 0.5) * 255.99) as u8,
-                        ((normal[2] * 0.5 + 0.5) * 255.99) as u8,
-                    ];
-
-                    let mut base_color = mat.base_col;
-                    if let Some(base_color_texture) = &mat.base_color_texture {
-                        let text = base_color_texture.get_pixel(tex[0], tex[1]);
-                        base_color = [
-                            base_color[0] * text[0],
-                            base_color[1] * text[1],
-                            base_color[2] * text[2],
-                            base_color[3] * text[3],
-                        ];
-                    };
-                    let light = [0.3, -0.7, 0.5];
-                    let intensity =
-                        (normal[0] * light[0] + normal[1] * light[1] + normal[2] * light[2])
-                            .clamp(0.2, 1.0);
-                    let color = rgb_to_u32(
-                        (base_color[0] * intensity * 255.99) as u8,
-                        (base_color[1] * intensity * 255.99) as u8,
-                        (base_color[2] * intensity * 255.99) as u8,
-                    );
-                    let color = blend_pixel(fb.get_pixel(x, y), color, base_color[3]);
-                    fb.set_pixel(
-                        x, y,
-                        color,
-                        //rgb_to_u32(norm_col[0] as u8, norm_col[1] as u8, norm_col[2] as u8),
-                    );
+                if !tile.depth_func.test(z, tile.depth[i]) {
+                    continue;
+                }
+                let w = [
+                    bary0 * v0_clip.1 * correction,
+                    bary1 * v1_clip.1 * correction,
+                    bary2 * v2_clip.1 * correction,
+                ];
+                let uv = interpolate(&v0.tex_coord, &v1.tex_coord, &v2.tex_coord, &w);
+                let uv_x = uv_at([a0 + step_x[0], a1 + step_x[1], a2 + step_x[2]]);
+                let uv_y = uv_at([a0 + step_y[0], a1 + step_y[1], a2 + step_y[2]]);
+                let tex = TexCoord {
+                    uv,
+                    ddx: [uv_x[0] - uv[0], uv_x[1] - uv[1]],
+                    ddy: [uv_y[0] - uv[0], uv_y[1] - uv[1]],
+                    tile: v0.tile,
+                };
+                // Cut out pixels neither write depth nor color, shadows included
+                if mat.alpha_mode == AlphaMode::Mask && coverage(mat, &tex) < mat.alpha_cutoff {
+                    continue;
                 }
+                if tile.depth_write {
+                    tile.depth[i] = z;
+                }
+                let Some(color) = tile.color.as_deref_mut() else {
+                    continue;
+                };
+                let mut normal = Vec3(interpolate(&v0.normal, &v1.normal, &v2.normal, &w));
+                // Back side of a double sided surface
+                if tri.is_back_facing() {
+                    normal = -normal;
+                }
+                let world_pos = Vec3(interpolate(&v0.world_pos, &v1.world_pos, &v2.world_pos, &w));
+                let tangent = interpolate(&v0.tangent, &v1.tangent, &v2.tangent, &w);
+                let lit = shade(
+                    lighting,
+                    mat,
+                    &world_pos,
+                    &normal.normalize(),
+                    &tangent,
+                    &tex,
+                );
+                // Blended in linear space, the color is only encoded on resolve
+                let alpha = match mat.alpha_mode {
+                    AlphaMode::Opaque | AlphaMode::Mask => 1.0,
+                    AlphaMode::Blend => lit[3],
+                };
+                color[i] = std::array::from_fn(|c| lit[c] * alpha + color[i][c] * (1.0 - alpha));
             }
         }
     }
 }
+// Binning rasterizer: triangles are transformed and set up once, sorted into bands of
+// TILE_HEIGHT rows and the bands are shaded in parallel. Every band keeps submission order,
+// so the result matches drawing the triangles one by one with draw_triangle.
+// Only opaque and masked meshes are drawn, blended ones go through draw_model_transparent.
 pub fn draw_model(
-    fb: &mut Framebuffer,
-    depth_buffer: &mut Framebuffer,
+    fb: &mut ColorBuffer,
+    depth_buffer: &mut DepthBuffer,
     model: &Model,
-    mvp: &Matrix,
-    invmod: &Matrix,
+    uniforms: &Uniforms,
 ) {
-    for mesh in &model.meshes {
-        for i in 0..(mesh.indices.len() / 3) {
-            let v0 = mesh.vertices[mesh.indices[i * 3] as usize];
-            let v1 = mesh.vertices[mesh.indices[i * 3 + 1] as usize];
-            let v2 = mesh.vertices[mesh.indices[i * 3 + 2] as usize];
+    let (width, height) = (fb.width(), fb.height());
+    rasterize_model(
+        Some(fb.data_mut()),
+        depth_buffer,
+        width,
+        height,
+        model,
+        uniforms,
+        Pass::Opaque,
+    );
+}
+// Draws the blended meshes of the model over what is already in the buffers. Triangles go
+// from back to front and are only tested against depth, so they don't hide each other.
+pub fn draw_model_transparent(
+    fb: &mut ColorBuffer,
+    depth_buffer: &mut DepthBuffer,
+    model: &Model,
+    uniforms: &Uniforms,
+) {
+    let (width, height) = (fb.width(), fb.height());
+    rasterize_model(
+        Some(fb.data_mut()),
+        depth_buffer,
+        width,
+        height,
+        model,
+        uniforms,
+        Pass::Transparent,
+    );
+}
+// Writes only depth of the model, for shadow maps. Blended meshes don't cast shadows.
+pub fn draw_model_depth(depth_buffer: &mut DepthBuffer, model: &Model, uniforms: &Uniforms) {
+    let (width, height) = (depth_buffer.width(), depth_buffer.height());
+    rasterize_model(
+        None,
+        depth_buffer,
+        width,
+        height,
+        model,
+        uniforms,
+        Pass::Opaque,
+    );
+}
+// Meshes drawn by one pass over the model
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pass {
+    // Opaque and masked meshes, writing depth if the buffer does
+    Opaque,
+    // Blended meshes back to front, only tested against depth
+    Transparent,
+}
+fn rasterize_model(
+    color: Option<&mut [[f32; 3]]>,
+    depth_buffer: &mut DepthBuffer,
+    width: usize,
+    height: usize,
+    model: &Model,
+    uniforms: &Uniforms,
+    pass: Pass,
+) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let blend = pass == Pass::Transparent;
+    let depth_write = depth_buffer.write && !blend;
+    // Transform, clip and set up
+    let frustum = Frustum::new(&uniforms.mvp);
+    let mut tris: Vec<(ScreenTriangle, usize)> = Vec::new();
+    for mesh in model.meshes.iter().filter(|mesh| {
+        (model.mats[mesh.material_idx].alpha_mode == AlphaMode::Blend) == blend
+            && frustum.intersects(&mesh.bounds)
+    }) {
+        let setup: Vec<(ScreenTriangle, usize)> = mesh
+            .indices
+            .par_chunks_exact(3)
+            .flat_map_iter(|idx| {
+                let poly = clip_triangle(
+                    ClipVertex::new(&mesh.vertices[idx[0] as usize], uniforms),
+                    ClipVertex::new(&mesh.vertices[idx[1] as usize], uniforms),
+                    ClipVertex::new(&mesh.vertices[idx[2] as usize], uniforms),
+                );
+                let mat = &model.mats[mesh.material_idx];
+                poly.triangles()
+                    .map(move |tri| ScreenTriangle::new(&tri, width, height))
+                    .filter(|tri| !tri.is_empty() && !tri.is_culled(uniforms.cull, mat))
+                    .map(|tri| (tri, mesh.material_idx))
+            })
+            .collect();
+        tris.extend(setup);
+    }
+    if blend {
+        // Farthest first by the mean clip w, which is the view depth whatever the depth
+        // function is. Ties keep submission order.
+        let depth = |tri: &ScreenTriangle| tri.verts.iter().map(|v| v.position[3]).sum::<f32>();
+        tris.sort_by(|(a, _), (b, _)| depth(b).total_cmp(&depth(a)));
+    }
+
+    // Bin
+    let mut bins: Vec<Vec<u32>> = vec![Vec::new(); height.div_ceil(TILE_HEIGHT)];
+    for (i, (tri, _)) in tris.iter().enumerate() {
+        let [_, ys, _, yl] = tri.bounds;
+        for bin in &mut bins[ys / TILE_HEIGHT..=(yl - 1) / TILE_HEIGHT] {
+            bin.push(i as u32);
+        }
+    }
+
+    // Shade
+    let band = width * TILE_HEIGHT;
+    let func = depth_buffer.func;
+    let colors: Vec<Option<&mut [[f32; 3]]>> = match color {
+        Some(color) => color.chunks_mut(band).map(Some).collect(),
+        None => bins.iter().map(|_| None).collect(),
+    };
+    colors
+        .into_par_iter()
+        .zip(depth_buffer.data_mut().par_chunks_mut(band))
+        .zip(bins.par_iter())
+        .enumerate()
+        .for_each(|(i, ((color, depth), bin))| {
+            let mut tile = Tile::new(color, depth, func, depth_write, width, i * TILE_HEIGHT);
+            for &t in bin {
+                let (tri, mat) = &tris[t as usize];
+                rasterize_triangle(&mut tile, tri, uniforms.lighting, &model.mats[*mat]);
+            }
+        });
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Mesh;
+    use crate::object::Object;
+
+    fn clip_vertex(position: [f32; 4], tex_coord: [f32; 2]) -> ClipVertex {
+        ClipVertex {
+            position: Vec4(position),
+            tex_coord,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn clip_keeps_inside_triangle() {
+        let tri = [
+            clip_vertex([0.0, 0.0, 0.5, 1.0], [0.0, 0.0]),
+            clip_vertex([0.5, 0.0, 0.5, 1.0], [1.0, 0.0]),
+            clip_vertex([0.0, 0.5, 0.5, 1.0], [0.0, 1.0]),
+        ];
+        let poly = clip_triangle(tri[0], tri[1], tri[2]);
+        assert_eq!(poly.vertices().len(), 3);
+        for (v, expected) in poly.vertices().iter().zip(&tri) {
+            assert_eq!(v.position.0, expected.position.0);
+            assert_eq!(v.tex_coord, expected.tex_coord);
+        }
+    }
+
+    #[test]
+    fn clip_drops_outside_triangle() {
+        // Right of the frustum
+        let poly = clip_triangle(
+            clip_vertex([2.0, 0.0, 0.5, 1.0], [0.0, 0.0]),
+            clip_vertex([3.0, 0.0, 0.5, 1.0], [1.0, 0.0]),
+            clip_vertex([2.0, 1.0, 0.5, 1.0], [0.0, 1.0]),
+        );
+        assert!(poly.vertices().is_empty());
+        assert_eq!(poly.triangles().count(), 0);
+    }
+
+    #[test]
+    fn clip_splits_triangle_crossing_near_plane() {
+        // First vertex is behind the near plane, the edges leaving it are cut in half
+        let poly = clip_triangle(
+            clip_vertex([0.0, 0.0, -0.5, 1.0], [0.0, 0.0]),
+            clip_vertex([0.5, 0.0, 0.5, 1.0], [1.0, 0.0]),
+            clip_vertex([0.0, 0.5, 0.5, 1.0], [0.0, 1.0]),
+        );
+        let expected = [
+            ([0.25, 0.0, 0.0, 1.0], [0.5, 0.0]),
+            ([0.5, 0.0, 0.5, 1.0], [1.0, 0.0]),
+            ([0.0, 0.5, 0.5, 1.0], [0.0, 1.0]),
+            ([0.0, 0.25, 0.0, 1.0], [0.0, 0.5]),
+        ];
+        assert_eq!(poly.vertices().len(), expected.len());
+        for (v, (position, tex_coord)) in poly.vertices().iter().zip(expected) {
+            let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6);
+            assert!(close(&v.position.0, &position));
+            assert!(close(&v.tex_coord, &tex_coord));
+        }
+        assert_eq!(poly.triangles().count(), 2);
+    }
+
+    fn draw_model_serial(
+        fb: &mut ColorBuffer,
+        depth_buffer: &mut DepthBuffer,
+        model: &Model,
+        uniforms: &Uniforms,
+    ) {
+        for mesh in &model.meshes {
+            for idx in mesh.indices.chunks_exact(3) {
+                draw_triangle(
+                    fb,
+                    depth_buffer,
+                    &mesh.vertices[idx[0] as usize],
+                    &mesh.vertices[idx[1] as usize],
+                    &mesh.vertices[idx[2] as usize],
+                    uniforms,
+                    &model.mats[mesh.material_idx],
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tiled_matches_serial() {
+        // Odd size so the last band is only partially covered
+        let (w, h) = (123, 77);
+        let view_proj = Mat4::projection(60.0f32.to_radians(), w as f32 / h as f32, 0.1, 300.0)
+            * Mat4::identity().rotate([0.3, 0.5, 0.0])
+            * Mat4::trans([0.0, 0.0, -4.0]);
+        let cubes: Vec<Object> = (0..6)
+            .map(|i| {
+                let mut cube = Object::cube(
+                    [i as f32 * 0.6 - 1.5, 0.2 * i as f32, i as f32 * 0.4],
+                    [0.4 * i as f32, 0.7, 0.1],
+                    [1.0, 1.5, 1.0],
+                    None,
+                );
+                cube.model.mats[0].base_col = [0.2 * i as f32, 0.5, 1.0, 1.0];
+                cube
+            })
+            .collect();
+
+        let lighting = Lighting::default();
+        let mut fb_serial = ColorBuffer::new(w, h);
+        let mut depth_serial = DepthBuffer::new(w, h);
+        let mut fb_tiled = ColorBuffer::new(w, h);
+        let mut depth_tiled = DepthBuffer::new(w, h);
+        for cube in &cubes {
+            let pos = [-cube.position[0], -cube.position[1], -cube.position[2]];
+            let mod_matrix = Mat4::trans(pos).rotate(cube.rotation) * Mat4::scale(cube.scale);
+            let uniforms = Uniforms::new(&view_proj, mod_matrix, &lighting);
+            draw_model_serial(&mut fb_serial, &mut depth_serial, &cube.model, &uniforms);
+            draw_model(&mut fb_tiled, &mut depth_tiled, &cube.model, &uniforms);
+        }
+
+        assert!(fb_serial.data().iter().any(|&p| p != [0.0; 3]));
+        assert_eq!(fb_serial.data(), fb_tiled.data());
+        assert_eq!(depth_serial.data(), depth_tiled.data());
+    }
+
+    #[test]
+    fn cull_modes() {
+        let (w, h) = (32, 32);
+        let lighting = Lighting::unlit();
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [x, y, 0.5],
+            ..Default::default()
+        };
+        // Counter-clockwise in glTF terms, so front facing
+        let front = [vertex(-0.5, -0.5), vertex(0.5, -0.5), vertex(0.0, 0.5)];
+        let drawn = |verts: &[Vertex; 3], cull: CullMode, double_sided: bool| {
+            let mut fb = ColorBuffer::new(w, h);
+            let mut depth_buffer = DepthBuffer::new(w, h);
+            let mat = Material {
+                double_sided,
+                ..Default::default()
+            };
+            let uniforms =
+                Uniforms::new(&Mat4::identity(), Mat4::identity(), &lighting).with_cull(cull);
+            let [v0, v1, v2] = verts;
+            draw_triangle(&mut fb, &mut depth_buffer, v0, v1, v2, &uniforms, &mat);
+            fb.data().iter().any(|&p| p != [0.0; 3])
+        };
+        let back = [front[0], front[2], front[1]];
+
+        assert!(drawn(&front, CullMode::Back, false));
+        assert!(!drawn(&back, CullMode::Back, false));
+        assert!(!drawn(&front, CullMode::Front, false));
+        assert!(drawn(&back, CullMode::Front, false));
+        assert!(drawn(&front, CullMode::None, false));
+        assert!(drawn(&back, CullMode::None, false));
+        assert!(drawn(&back, CullMode::Back, true));
+    }
+
+    #[test]
+    fn blended_triangles_sort_back_to_front() {
+        let (w, h) = (16, 16);
+        let lighting = Lighting::unlit();
+        let mesh = |z: f32, material_idx: usize| {
+            let vertex = |x: f32, y: f32| Vertex {
+                position: [x, y, z],
+                normal: [0.0, 0.0, 1.0],
+                ..Default::default()
+            };
+            let vertices = vec![
+                vertex(-1.0, -1.0),
+                vertex(1.0, -1.0),
+                vertex(1.0, 1.0),
+                vertex(-1.0, 1.0),
+            ];
+            Mesh::new(vertices, vec![0, 1, 2, 0, 2, 3], material_idx)
+        };
+        let blend = |base_col: [f32; 4]| Material {
+            base_col,
+            alpha_mode: AlphaMode::Blend,
+            double_sided: true,
+            ..Default::default()
+        };
+        // The near red quad is submitted first, but has to be drawn over the far blue one
+        let model = Model::new(
+            vec![mesh(-2.0, 0), mesh(-4.0, 1)],
+            vec![blend([1.0, 0.0, 0.0, 0.5]), blend([0.0, 0.0, 1.0, 0.5])],
+        );
+        let fov = std::f32::consts::FRAC_PI_2;
+        // Same order whichever way depth goes
+        let passes = [
+            (
+                Mat4::projection(fov, 1.0, 0.1, 10.0),
+                DepthBuffer::new(w, h),
+            ),
+            (
+                Mat4::projection_reverse_z(fov, 1.0, 0.1, 10.0),
+                DepthBuffer::reverse_z(w, h),
+            ),
+        ];
+        for (proj, mut depth_buffer) in passes {
+            let mut fb = ColorBuffer::new(w, h);
+            let clear = depth_buffer.data().to_vec();
+            let uniforms = Uniforms::new(&proj, Mat4::identity(), &lighting);
+
+            // Nothing of the model is opaque
+            draw_model(&mut fb, &mut depth_buffer, &model, &uniforms);
+            assert!(fb.data().iter().all(|&p| p == [0.0; 3]));
 
-            draw_triangle(
-                fb,
-                depth_buffer,
-                &v0,
-                &v1,
-                &v2,
-                mvp,
-                invmod,
-                &model.mats[mesh.material_idx],
-            );
+            draw_model_transparent(&mut fb, &mut depth_buffer, &model, &uniforms);
+            let [r, g, b] = fb.get(7, 8);
+            assert!((r - 0.5).abs() < 1e-4 && g == 0.0 && (b - 0.25).abs() < 1e-4);
+            assert_eq!(depth_buffer.data(), clear);
         }
     }
 }
@@ -0,0 +1,110 @@
+use std::fmt;
+
+// Everything that can go wrong while loading assets or opening a window. Asset errors carry
+// the path of the file that failed.
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Decode {
+        path: String,
+        message: String,
+    },
+    UnsupportedPrimitiveMode {
+        path: String,
+        mode: gltf::mesh::Mode,
+    },
+    MissingAttribute {
+        path: String,
+        attribute: &'static str,
+    },
+    // Vertex attribute with a different number of elements than POSITION
+    AttributeCountMismatch {
+        path: String,
+        attribute: &'static str,
+    },
+    InvalidIndex {
+        path: String,
+        index: u32,
+        vertex_count: usize,
+    },
+    // Malformed data file, line counts from 1
+    Parse {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    Window(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{path}: {source}"),
+            Error::Decode { path, message } => write!(f, "{path}: failed to decode: {message}"),
+            Error::UnsupportedPrimitiveMode { path, mode } => {
+                write!(f, "{path}: unsupported primitive mode {mode:?}")
+            }
+            Error::MissingAttribute { path, attribute } => {
+                write!(f, "{path}: primitive is missing {attribute} attribute")
+            }
+            Error::AttributeCountMismatch { path, attribute } => {
+                write!(f, "{path}: {attribute} count doesn't match POSITION")
+            }
+            Error::InvalidIndex {
+                path,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "{path}: index {index} is out of range for {vertex_count} vertices"
+            ),
+            Error::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{path}:{line}: {message}"),
+            Error::Window(message) => write!(f, "failed to create window: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn from_image(path: &str, err: image::ImageError) -> Self {
+        match err {
+            image::ImageError::IoError(source) => Error::Io {
+                path: path.to_string(),
+                source,
+            },
+            err => Error::Decode {
+                path: path.to_string(),
+                message: err.to_string(),
+            },
+        }
+    }
+    pub(crate) fn from_gltf(path: &str, err: gltf::Error) -> Self {
+        match err {
+            gltf::Error::Io(source) => Error::Io {
+                path: path.to_string(),
+                source,
+            },
+            err => Error::Decode {
+                path: path.to_string(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    error::Error,
+    window::{Framebuffer, Input, Surface},
+};
+use std::path::{Path, PathBuf};
+
+// Offscreen surface without a display. Every presented frame can be written to a PNG and the
+// surface asks to close after the given number of frames, or when a frame can't be written.
+pub struct Headless {
+    framebuffer: Framebuffer,
+    input: Input,
+    frame: usize,
+    frames: Option<usize>,
+    output: Option<PathBuf>,
+    error: Option<Error>,
+}
+impl Headless {
+    pub fn new(w: usize, h: usize) -> Self {
+        Headless {
+            framebuffer: Framebuffer::new(w, h),
+            input: Input::new(),
+            frame: 0,
+            frames: None,
+            output: None,
+            error: None,
+        }
+    }
+    // Closes after `frames` presented frames
+    pub fn with_frames(mut self, frames: usize) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+    // Saves every presented frame as frame_NNNN.png into dir
+    pub fn with_output<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.output = Some(dir.into());
+        self
+    }
+    // Number of frames presented so far
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+    // Why the surface closed early
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+    fn save_frame(&self, dir: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(dir).map_err(|source| Error::Io {
+            path: dir.display().to_string(),
+            source,
+        })?;
+        let path = dir.join(format!("frame_{:04}.png", self.frame));
+        self.framebuffer
+            .save_png(&path)
+            .map_err(|err| Error::from_image(&path.display().to_string(), err))
+    }
+}
+impl Surface for Headless {
+    fn framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    fn input(&mut self) -> &mut Input {
+        &mut self.input
+    }
+
+    fn should_close(&self) -> bool {
+        self.error.is_some() || self.frames.is_some_and(|frames| self.frame >= frames)
+    }
+
+    fn update(&mut self) {
+        if let Some(dir) = &self.output {
+            if let Err(err) = self.save_frame(dir) {
+                self.error = Some(err);
+            }
+        }
+        self.frame += 1;
+        self.input.process_keys(Default::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwritable_output_closes_with_error() {
+        // A file where the output directory should be
+        let file = std::env::temp_dir().join(format!("headless_output_{}", std::process::id()));
+        std::fs::write(&file, b"").unwrap();
+        let mut surface = Headless::new(4, 4).with_frames(3).with_output(&file);
+        assert!(!surface.should_close());
+        surface.update();
+        assert!(surface.should_close());
+        assert!(matches!(surface.error(), Some(Error::Io { .. })));
+        std::fs::remove_file(&file).unwrap();
+    }
+}
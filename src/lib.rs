@@ -0,0 +1,22 @@
+pub mod atlas;
+pub mod block;
+pub mod bounds;
+pub mod camera;
+pub mod color;
+pub mod controls;
+pub mod depth;
+pub mod draw;
+pub mod error;
+pub mod headless;
+pub mod light;
+pub mod mesher;
+pub mod model;
+pub mod noise;
+pub mod object;
+pub mod physics;
+pub mod shadow;
+pub mod terrain;
+pub mod texture;
+pub mod types;
+pub mod voxel;
+pub mod window;
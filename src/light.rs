@@ -0,0 +1,348 @@
+use crate::{shadow::ShadowMap, types::Vec3};
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    // Light coming from infinity, dir is the direction it travels in
+    Directional {
+        dir: [f32; 3],
+        color: [f32; 3],
+    },
+    // Falls off as 1 / (constant + linear * d + quadratic * d^2)
+    Point {
+        pos: [f32; 3],
+        color: [f32; 3],
+        attenuation: [f32; 3],
+    },
+    // Point light limited to a cone around dir, fading out between the inner and outer angle
+    // (half angles in radians)
+    Spot {
+        pos: [f32; 3],
+        dir: [f32; 3],
+        color: [f32; 3],
+        attenuation: [f32; 3],
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+impl Light {
+    // Unit vector from the point towards the light and the light color arriving there
+    pub fn incident(&self, pos: &Vec3) -> (Vec3, [f32; 3]) {
+        match *self {
+            Light::Directional { dir, color } => (-Vec3(dir).normalize(), color),
+            Light::Point {
+                pos: light_pos,
+                color,
+                attenuation,
+            } => {
+                let to_light = Vec3(light_pos) - *pos;
+                let att = falloff(to_light.length(), attenuation);
+                (to_light.normalize(), scale(color, att))
+            }
+            Light::Spot {
+                pos: light_pos,
+                dir,
+                color,
+                attenuation,
+                inner_angle,
+                outer_angle,
+            } => {
+                let to_light = Vec3(light_pos) - *pos;
+                let l = to_light.normalize();
+                let cos = (-l).dot(&Vec3(dir).normalize());
+                let (cos_inner, cos_outer) = (inner_angle.cos(), outer_angle.cos());
+                let t = ((cos - cos_outer) / (cos_inner - cos_outer).max(1e-4)).clamp(0.0, 1.0);
+                let cone = t * t * (3.0 - 2.0 * t);
+                let att = falloff(to_light.length(), attenuation) * cone;
+                (l, scale(color, att))
+            }
+        }
+    }
+}
+fn falloff(d: f32, attenuation: [f32; 3]) -> f32 {
+    1.0 / (attenuation[0] + attenuation[1] * d + attenuation[2] * d * d).max(1e-4)
+}
+fn scale(color: [f32; 3], s: f32) -> [f32; 3] {
+    [color[0] * s, color[1] * s, color[2] * s]
+}
+
+// Lights of the frame together with the ambient term and the eye position for specular
+#[derive(Clone, Debug)]
+pub struct Lighting {
+    pub lights: Vec<Light>,
+    pub ambient: [f32; 3],
+    pub eye: [f32; 3],
+    pub shadow: Option<ShadowMap>,
+}
+impl Default for Lighting {
+    // Sun from above, bright enough that fully lit faces reach the base color
+    fn default() -> Self {
+        Lighting {
+            lights: vec![Light::Directional {
+                dir: [-0.3, 0.7, -0.5],
+                color: [0.8; 3],
+            }],
+            ambient: [0.2; 3],
+            eye: [0.0; 3],
+            shadow: None,
+        }
+    }
+}
+impl Lighting {
+    // No lights and full ambient, so surfaces show their base color
+    pub fn unlit() -> Self {
+        Lighting {
+            lights: Vec::new(),
+            ambient: [1.0; 3],
+            eye: [0.0; 3],
+            shadow: None,
+        }
+    }
+    pub fn with_shadow(mut self, shadow: ShadowMap) -> Self {
+        self.shadow = Some(shadow);
+        self
+    }
+    // Aims the enabled shadow map at its directional light around center and clears it, the
+    // shadow casters are drawn into the returned map before shading
+    pub fn begin_shadows(&mut self, center: [f32; 3]) -> Option<&mut ShadowMap> {
+        let shadow = self.shadow.as_mut().filter(|shadow| shadow.enabled)?;
+        let Some(Light::Directional { dir, .. }) = self.lights.get(shadow.light) else {
+            return None;
+        };
+        shadow.begin(*dir, center);
+        Some(shadow)
+    }
+    // Shadowing of the light with index i at pos
+    fn visibility(&self, i: usize, pos: &Vec3, n_dot_l: f32) -> f32 {
+        match &self.shadow {
+            Some(shadow) if shadow.enabled && shadow.light == i => shadow.visibility(pos, n_dot_l),
+            _ => 1.0,
+        }
+    }
+    // Ambient light left after occlusion
+    fn ambient(&self, albedo: [f32; 3], occlusion: f32) -> [f32; 3] {
+        std::array::from_fn(|c| self.ambient[c] * albedo[c] * occlusion)
+    }
+    // Blinn-Phong diffuse and specular of every light plus ambient, in linear 0..1 color
+    pub fn shade(
+        &self,
+        pos: &Vec3,
+        normal: &Vec3,
+        albedo: [f32; 3],
+        specular: f32,
+        shininess: f32,
+        occlusion: f32,
+    ) -> [f32; 3] {
+        let mut col = self.ambient(albedo, occlusion);
+        let view = (Vec3(self.eye) - *pos).normalize();
+        for (i, light) in self.lights.iter().enumerate() {
+            let (l, light_col) = light.incident(pos);
+            let n_dot_l = normal.dot(&l);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            let light_col = scale(light_col, self.visibility(i, pos, n_dot_l));
+            let half = (l + view).normalize();
+            let spec = specular * normal.dot(&half).max(0.0).powf(shininess);
+            for c in 0..3 {
+                col[c] += light_col[c] * (albedo[c] * n_dot_l + spec);
+            }
+        }
+        col
+    }
+    // Cook-Torrance with GGX distribution, Smith-Schlick geometry and Schlick Fresnel.
+    // Light colors are in the same units as for Blinn-Phong, so the Lambert term is not
+    // divided by pi and the specular one is multiplied by it instead.
+    pub fn shade_pbr(
+        &self,
+        pos: &Vec3,
+        normal: &Vec3,
+        albedo: [f32; 3],
+        metallic: f32,
+        roughness: f32,
+        occlusion: f32,
+    ) -> [f32; 3] {
+        let mut col = self.ambient(albedo, occlusion);
+        let view = (Vec3(self.eye) - *pos).normalize();
+        let n_dot_v = normal.dot(&view).max(1e-4);
+        let alpha = (roughness * roughness).max(1e-3);
+        let alpha2 = alpha * alpha;
+        let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+        let geometry_v = smith(n_dot_v, k);
+        let f0: [f32; 3] = std::array::from_fn(|c| 0.04 + (albedo[c] - 0.04) * metallic);
+        for (i, light) in self.lights.iter().enumerate() {
+            let (l, light_col) = light.incident(pos);
+            let n_dot_l = normal.dot(&l);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            let light_col = scale(light_col, self.visibility(i, pos, n_dot_l));
+            let half = (l + view).normalize();
+            let n_dot_h = normal.dot(&half).max(0.0);
+            let v_dot_h = view.dot(&half).max(0.0);
+
+            let distribution = ggx(n_dot_h, alpha2);
+            let geometry = geometry_v * smith(n_dot_l, k);
+            for c in 0..3 {
+                let fresnel = schlick(f0[c], v_dot_h);
+                let spec = distribution * geometry * fresnel / (4.0 * n_dot_v * n_dot_l);
+                let diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo[c];
+                col[c] += light_col[c] * (diffuse + PI * spec) * n_dot_l;
+            }
+        }
+        col
+    }
+}
+// GGX normal distribution, alpha2 is roughness to the fourth
+fn ggx(n_dot_h: f32, alpha2: f32) -> f32 {
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * d * d)
+}
+// Smith-Schlick shadowing of one direction
+fn smith(n_dot: f32, k: f32) -> f32 {
+    n_dot / (n_dot * (1.0 - k) + k)
+}
+// Reflectance between f0 head on and 1 at grazing angles
+fn schlick(f0: f32, v_dot_h: f32) -> f32 {
+    f0 + (1.0 - f0) * (1.0 - v_dot_h).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn point_light_attenuates_with_distance() {
+        let light = Light::Point {
+            pos: [0.0; 3],
+            color: [1.0, 0.5, 0.0],
+            attenuation: [1.0, 0.5, 0.25],
+        };
+        let (l, color) = light.incident(&Vec3([2.0, 0.0, 0.0]));
+        assert_eq!(l.0, [-1.0, 0.0, 0.0]);
+        // 1 / (1 + 0.5 * 2 + 0.25 * 4)
+        assert!(close(color[0], 1.0 / 3.0) && close(color[1], 0.5 / 3.0));
+        assert_eq!(color[2], 0.0);
+        let (_, far) = light.incident(&Vec3([0.0, 0.0, 10.0]));
+        assert!(close(far[0], 1.0 / 31.0));
+        // Zero attenuation doesn't divide by zero
+        let unlit = Light::Point {
+            pos: [0.0; 3],
+            color: [1.0; 3],
+            attenuation: [0.0; 3],
+        };
+        assert!(unlit.incident(&Vec3([1.0, 0.0, 0.0])).1[0].is_finite());
+    }
+
+    // Spot light 5 above the origin pointing down, the color reaching the ground at the angle
+    fn spot_at(inner_angle: f32, outer_angle: f32, angle: f32) -> f32 {
+        let light = Light::Spot {
+            pos: [0.0, 5.0, 0.0],
+            dir: [0.0, -1.0, 0.0],
+            color: [1.0; 3],
+            attenuation: [1.0, 0.0, 0.0],
+            inner_angle,
+            outer_angle,
+        };
+        light.incident(&Vec3([5.0 * angle.tan(), 0.0, 0.0])).1[0]
+    }
+
+    #[test]
+    fn spot_light_fades_between_cone_angles() {
+        assert!(close(spot_at(0.2, 0.4, 0.0), 1.0));
+        assert!(close(spot_at(0.2, 0.4, 0.19), 1.0));
+        assert_eq!(spot_at(0.2, 0.4, 0.41), 0.0);
+        assert_eq!(spot_at(0.2, 0.4, 1.0), 0.0);
+        // Smoothstep is half way where the cosine is
+        let middle = ((0.2f32.cos() + 0.4f32.cos()) / 2.0).acos();
+        assert!(close(spot_at(0.2, 0.4, middle), 0.5));
+        let fade: Vec<f32> = (0..=10)
+            .map(|i| spot_at(0.2, 0.4, 0.2 + i as f32 * 0.02))
+            .collect();
+        assert!(fade.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn spot_light_with_equal_angles_has_hard_edge() {
+        assert_eq!(spot_at(0.3, 0.3, 0.29), 1.0);
+        assert_eq!(spot_at(0.3, 0.3, 0.31), 0.0);
+        assert!(spot_at(0.3, 0.3, 0.3).is_finite());
+    }
+
+    #[test]
+    fn ggx_is_normalized() {
+        // Projected area of the microfacets integrates to 1 over the hemisphere
+        for roughness in [0.3f32, 0.6, 1.0] {
+            let alpha2 = roughness.powi(4);
+            let steps = 4000;
+            let dtheta = PI / 2.0 / steps as f32;
+            let integral: f32 = (0..steps)
+                .map(|i| {
+                    let theta = (i as f32 + 0.5) * dtheta;
+                    let cos = theta.cos();
+                    ggx(cos, alpha2) * cos * 2.0 * PI * theta.sin() * dtheta
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 0.01, "{roughness}: {integral}");
+        }
+    }
+
+    #[test]
+    fn smith_and_schlick_limits() {
+        for k in [0.125, 0.5] {
+            assert!(close(smith(1.0, k), 1.0));
+            assert_eq!(smith(0.0, k), 0.0);
+        }
+        assert!(close(schlick(0.04, 1.0), 0.04));
+        assert!(close(schlick(0.04, 0.0), 1.0));
+        assert!(close(schlick(0.9, 0.0), 1.0));
+        assert!(schlick(0.04, 0.2) > schlick(0.04, 0.8));
+    }
+
+    // Sun straight down on an upward facing point seen from the eye
+    fn pbr(eye: [f32; 3], albedo: [f32; 3], metallic: f32, roughness: f32) -> [f32; 3] {
+        let lighting = Lighting {
+            lights: vec![Light::Directional {
+                dir: [0.0, -1.0, 0.0],
+                color: [1.0; 3],
+            }],
+            ambient: [0.0; 3],
+            eye,
+            shadow: None,
+        };
+        let (pos, normal) = (Vec3([0.0; 3]), Vec3([0.0, 1.0, 0.0]));
+        lighting.shade_pbr(&pos, &normal, albedo, metallic, roughness, 1.0)
+    }
+
+    #[test]
+    fn pbr_energy_limits() {
+        let above = [0.0, 1.0, 0.0];
+        // Rough white dielectric reflects at most the incoming light
+        let rough = pbr(above, [1.0; 3], 0.0, 1.0);
+        assert!(rough.iter().all(|&c| c > 0.9 && c <= 1.0), "{rough:?}");
+        // Black metal has neither diffuse nor reflectance
+        assert_eq!(pbr(above, [0.0; 3], 1.0, 0.5), [0.0; 3]);
+        // Metal has no diffuse, away from the highlight it's darker than a dielectric
+        let side = [1.0, 0.2, 0.0];
+        assert!(pbr(side, [0.5; 3], 1.0, 0.5)[0] < pbr(side, [0.5; 3], 0.0, 0.5)[0]);
+        // Mirror concentrates everything into the reflection direction without blowing up
+        let mirror = pbr(above, [0.5; 3], 1.0, 0.0);
+        assert!(mirror.iter().all(|c| c.is_finite() && *c > 1.0));
+        assert!(pbr(side, [0.5; 3], 1.0, 0.0)[0] < 1e-3);
+    }
+
+    #[test]
+    fn directional_light_comes_against_its_direction() {
+        let light = Light::Directional {
+            dir: [0.0, -2.0, 0.0],
+            color: [0.5; 3],
+        };
+        let (l, color) = light.incident(&Vec3([3.0, 4.0, 5.0]));
+        assert_eq!(l.0, [0.0, 1.0, 0.0]);
+        assert_eq!(color, [0.5; 3]);
+    }
+}
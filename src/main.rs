@@ -1,30 +1,89 @@
-mod controls;
-mod draw;
-mod model;
-mod object;
-mod physics;
-mod texture;
-mod types;
-mod window;
-
 use std::time::SystemTime;
 
-use controls::{rot_to_dir, Action, Controls};
-use draw::draw_line;
-use model::load_model;
-use physics::{GravType, Physics};
-use types::Matrix;
-use window::{Framebuffer, Window};
+use graphics_2nd_try::block::BlockRegistry;
+use graphics_2nd_try::camera::Camera;
+use graphics_2nd_try::color::{ColorBuffer, ToneMapping};
+use graphics_2nd_try::controls::{rot_to_dir, Action, Controls};
+use graphics_2nd_try::depth::DepthBuffer;
+use graphics_2nd_try::draw::draw_line;
+use graphics_2nd_try::headless::Headless;
+use graphics_2nd_try::light::Lighting;
+use graphics_2nd_try::model::load_model_or_placeholder;
+use graphics_2nd_try::object;
+use graphics_2nd_try::physics::{GravType, Physics};
+use graphics_2nd_try::shadow::ShadowMap;
+use graphics_2nd_try::terrain::{Terrain, TerrainBlocks};
+#[cfg(feature = "matrix-stats")]
+use graphics_2nd_try::types;
+use graphics_2nd_try::voxel::{VoxelWorld, AIR};
+use graphics_2nd_try::window::{Surface, Window};
+use minifb::Key;
+
+// Time step used offscreen, so rendered frames don't depend on how fast the machine is
+const HEADLESS_DELTAT: f32 = 16.0;
+const SHADOW_RESOLUTION: usize = 1024;
+const BLOCKS_PATH: &str = "./assets/blocks.txt";
+// Columns of terrain generated around the origin in every direction
+const TERRAIN_RADIUS: i32 = 32;
 
 fn rgb_to_u32(r: u8, g: u8, b: u8) -> u32 {
     let (r, g, b) = (r as u32, g as u32, b as u32);
     (r << 16) | (g << 8) | b
 }
+// Usage: graphics_2nd_try [--headless <frames>] [--out <dir>] [--seed <seed>] [--greedy]
+// With --headless the scene is rendered offscreen and every frame is saved as PNG into
+// the output directory (./frames by default). The terrain is generated from the seed, a new
+// one every run unless given, offscreen runs use 0 so their frames stay the same. With
+// --greedy the faces of the terrain are merged into larger rectangles.
 fn main() {
-    let mut window = Window::new("asdf", 512, 512);
-
+    let args: Vec<String> = std::env::args().collect();
+    let arg_value = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let seed = arg_value("--seed").map(|s| {
+        s.parse::<u64>()
+            .unwrap_or_else(|_| exit_with_error(&format!("invalid seed {s}")))
+    });
+    let greedy = args.iter().any(|a| a == "--greedy");
+    if args.iter().any(|a| a == "--headless") {
+        let frames = arg_value("--headless").map_or("", String::as_str);
+        let frames = frames
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .unwrap_or_else(|| exit_with_error(&format!("invalid frame count {frames}")));
+        let out = arg_value("--out").map_or("./frames", String::as_str);
+        let mut surface = Headless::new(512, 512).with_frames(frames).with_output(out);
+        run(
+            &mut surface,
+            Some(HEADLESS_DELTAT),
+            seed.unwrap_or(0),
+            greedy,
+        );
+        if let Some(err) = surface.take_error() {
+            exit_with_error(&err.to_string());
+        }
+    } else {
+        let seed = seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+            now.unwrap().as_nanos() as u64
+        });
+        println!("Seed {seed}");
+        let mut window = Window::new("asdf", 512, 512);
+        run(&mut window, None, seed, greedy);
+    }
+}
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+// Runs the game loop on the surface, with fixed_deltat the simulation advances by that
+// many milliseconds every frame instead of measured time
+fn run(window: &mut impl Surface, fixed_deltat: Option<f32>, seed: u64, greedy: bool) {
     // let mut helmet = object::Object::new(
-    //     load_model("./assets/helmet/DamagedHelmet.gltf"),
+    //     load_model_or_placeholder("./assets/helmet/DamagedHelmet.gltf"),
     //     [0.0, 0.0, 0.0],
     //     [
     //         0.0f32.to_radians(),
@@ -34,7 +93,7 @@ fn main() {
     //     [1.0, 1.0, 1.0],
     // );
     let mut shark = object::Object::new(
-        load_model("./assets/blahaj.glb"),
+        load_model_or_placeholder("./assets/blahaj.glb"),
         [0.0, -2.0, 50.0],
         [
             0.0f32.to_radians(),
@@ -46,7 +105,7 @@ fn main() {
     .with_physics(Physics::new(2.0e3, false, GravType::Space));
     shark.physics.as_mut().unwrap().lock().unwrap().veloc[0] = 0.0895;
     let ball = object::Object::new(
-        load_model("./assets/ball/ball.gltf"),
+        load_model_or_placeholder("./assets/ball/ball.gltf"),
         [0.0, -2.0, 0.0],
         [
             0.0f32.to_radians(),
@@ -56,64 +115,102 @@ fn main() {
         [10.0, 10.0, 10.0],
     )
     .with_physics(Physics::new(6.0e9, false, GravType::Space));
-    let mut world: Vec<object::Object> = Vec::new();
-    world.push(ball);
-    let mut dur: Vec<f32> = vec![1000.0; world.len()];
+    let mut world: Vec<object::Object> = vec![ball];
+    let registry = BlockRegistry::load(BLOCKS_PATH).unwrap_or_else(|err| panic!("{err}"));
+    let block = |name: &str| {
+        registry
+            .id(name)
+            .unwrap_or_else(|| panic!("{BLOCKS_PATH}: missing block {name}"))
+    };
+    let blocks = TerrainBlocks {
+        grass: block("grass"),
+        dirt: block("dirt"),
+        stone: block("stone"),
+        log: block("log"),
+        leaves: block("leaves"),
+    };
+    let mut voxels = VoxelWorld::new(registry).with_greedy(greedy);
+    let r = TERRAIN_RADIUS;
+    Terrain::new(seed, blocks).generate(&mut voxels, [-r, -r], [r, r]);
+    // Block being mined and milliseconds left until it breaks
+    let mut mining: Option<([i32; 3], f32)> = None;
+    // Block placed by the player, the last one mined
+    let mut held = blocks.grass;
 
     let timer = SystemTime::now();
-    let mut depth_buffer =
-        Framebuffer::new(window.framebuffer().width(), window.framebuffer().height());
+    let (width, height) = (window.framebuffer().width(), window.framebuffer().height());
+    let mut depth_buffer = DepthBuffer::new(width, height);
+    let mut color_buffer = ColorBuffer::new(width, height);
+    let mut tone_mapping = ToneMapping::default();
     let mut player = Controls::new();
-    let mut deltat = 0.1;
+    // Start above the ground, or a tree growing there
+    if let Some(hit) = voxels.raycast_solid(player.pos, [0.0, -1.0, 0.0], 200.0) {
+        player.pos[1] = hit.pos[1] as f32 + 2.0;
+    }
+    let mut camera = Camera::new(player.pos, player.rot);
+    let mut lighting = Lighting::default().with_shadow(ShadowMap::new(SHADOW_RESOLUTION));
+    let mut deltat = fixed_deltat.unwrap_or(0.1);
+    let mut sim_time = 0.0;
 
     while !window.should_close() {
         let start = timer.elapsed().unwrap().as_millis();
         player.update(window.input(), deltat);
+        // Toggle shadows
+        if window.input().is_key_down(Key::F) {
+            if let Some(shadow) = lighting.shadow.as_mut() {
+                shadow.enabled = !shadow.enabled;
+            }
+        }
+        // Cycle tone mapping
+        if window.input().is_key_down(Key::T) {
+            tone_mapping = match tone_mapping {
+                ToneMapping::Aces => ToneMapping::Reinhard,
+                ToneMapping::Reinhard => ToneMapping::Clamp,
+                ToneMapping::Clamp => ToneMapping::Aces,
+            };
+        }
 
         // Process buffers
         let fb = window.framebuffer();
         if depth_buffer.width() != fb.width() || depth_buffer.height() != fb.height() {
-            depth_buffer = Framebuffer::new(fb.width(), fb.height());
+            depth_buffer.resize(fb.width(), fb.height());
+            color_buffer.resize(fb.width(), fb.height());
         }
-        fb.clear(rgb_to_u32(0, 0, 0));
-        depth_buffer.clear(u32::MAX);
+        color_buffer.clear([0.0; 3]);
+        depth_buffer.clear();
 
         //View and projection
-        let view_matrix = Matrix::identity().rotate(player.rot) * Matrix::trans(player.pos);
-        let proj_matrix = Matrix::projection(
-            60.0f32.to_radians(),
-            fb.width() as f32 / fb.height() as f32,
-            0.1,
-            300.0,
-        );
-        let view_proj = proj_matrix * view_matrix;
+        camera.pos = player.pos;
+        camera.rot = player.rot;
+        let view_proj = camera.view_proj(fb.width(), fb.height());
+        lighting.eye = camera.eye();
 
         // Rotate objects
 
-        let elapsed = timer.elapsed().unwrap().as_secs_f32();
+        let elapsed = match fixed_deltat {
+            Some(_) => sim_time,
+            None => timer.elapsed().unwrap().as_secs_f32(),
+        };
         shark.rotation[1] = elapsed;
 
         //Render objects
 
         // Raycast
         let mut groundd = 99.9;
-        let mut td = (6.5, [0.0; 3]);
-        let mut touch: Option<usize> = None;
         let shark_dir = rot_to_dir([
             shark.rotation[0],
             shark.rotation[1] - 90.0f32.to_radians(),
             shark.rotation[2],
         ]);
         let pdir = player.rot_dir();
-        for i in 0..world.len() {
-            let cube = &mut world[i];
+        for object in world.iter() {
             // Ground check
-            let d = cube
+            let d = object
                 .raycast(
                     player.pos,
                     [0.0, 1.0, -0.0],
                     2.5,
-                    fb,
+                    &mut color_buffer,
                     &mut depth_buffer,
                     &view_proj,
                     false,
@@ -123,28 +220,19 @@ fn main() {
                 groundd = d;
             }
             // Point
-            cube.raycast(
+            object.raycast(
                 shark.position,
                 shark_dir,
                 16.5,
-                fb,
-                &mut depth_buffer,
-                &view_proj,
-                true,
-            );
-            let t = cube.raycast(
-                player.pos,
-                pdir,
-                6.5,
-                fb,
+                &mut color_buffer,
                 &mut depth_buffer,
                 &view_proj,
                 true,
             );
-            if t.0 < td.0 {
-                td = t;
-                touch = Some(i);
-            }
+        }
+        // Object::raycast walks against the direction, so the block rays are flipped
+        if let Some(hit) = voxels.raycast_solid(player.pos, [0.0, -1.0, 0.0], 2.5) {
+            groundd = groundd.min(hit.dist);
         }
         if groundd >= 2.5 {
             //player.pos[1] -= 0.001 * deltat;
@@ -152,83 +240,79 @@ fn main() {
             player.pos[1] += 0.005 * deltat;
         }
         // Interact with blocks
-        if touch.is_some() {
-            let scale: Matrix = vec![
-                vec![world[touch.unwrap()].scale[0], 0.0, 0.0, 0.0],
-                vec![0.0, world[touch.unwrap()].scale[1], 0.0, 0.0],
-                vec![0.0, 0.0, world[touch.unwrap()].scale[2], 0.0],
-                vec![0.0, 0.0, 0.0, 1.0],
-            ]
-            .into();
-            let mut pos_matrix = Matrix::trans(world[touch.unwrap()].position) * -1.0;
-            pos_matrix = pos_matrix.rotate(world[touch.unwrap()].rotation) * scale;
-            for mesh in world[touch.unwrap()].model.meshes.iter() {
-                for i in 0..mesh.indices.len() / 3 {
-                    let scal = Matrix::identity() * 1.05;
-                    let v1 = &pos_matrix
-                        * (&scal * mesh.vertices[mesh.indices[3 * i + 1] as usize].position);
-                    let v2 = &pos_matrix
-                        * (&scal * mesh.vertices[mesh.indices[3 * i + 2] as usize].position);
+        let target = voxels.raycast(player.pos, pdir.map(|c| -c), 6.5);
+        if let Some(hit) = target {
+            // Outline of the block, slightly larger so it isn't hidden by the faces
+            let corner = |i: usize| {
+                let offset = [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|b| b as f32 * 1.05 - 0.525);
+                std::array::from_fn(|j| -(hit.pos[j] as f32 + offset[j]))
+            };
+            for (i, j) in (0..8).flat_map(|i| [1, 2, 4].map(|bit| (i, i | bit))) {
+                if i != j {
                     draw_line(
-                        fb,
+                        &mut color_buffer,
                         &mut depth_buffer,
-                        &v1,
-                        &v2,
+                        &corner(i),
+                        &corner(j),
                         &view_proj,
                         &[255, 255, 255],
                     );
                 }
             }
-            let mut mining = false;
             match player.action {
                 Action::Placing => {
-                    let hit = world[touch.unwrap()].position;
-                    let xdif = td.1[0] - hit[0];
-                    let ydif = td.1[1] - hit[1];
-                    let zdif = td.1[2] - hit[2];
-                    let dir = if xdif.abs() > ydif.abs() {
-                        if xdif.abs() > zdif.abs() {
-                            [1.0 * xdif.signum(), 0.0, 0.0]
-                        } else {
-                            [0.0, 0.0, 1.0 * zdif.signum()]
-                        }
-                    } else if ydif.abs() > zdif.abs() {
-                        [0.0, 1.0 * ydif.signum(), 0.0]
-                    } else {
-                        [0.0, 0.0, 1.0 * zdif.signum()]
-                    };
-                    let new_pos = [hit[0] + dir[0], hit[1] + dir[1], hit[2] + dir[2]];
-                    world.push(object::Object::cube(
-                        new_pos,
-                        [0.0f32.to_radians(), 0.0f32.to_radians(), 0.0],
-                        [1.0, 1.0, 1.0],
-                        Some("./assets/grass.png"),
-                    ));
-                    dur.push(1000.0);
+                    let pos = std::array::from_fn(|i| hit.pos[i] + hit.normal[i]);
+                    if voxels.get(pos) == AIR {
+                        voxels.set(pos, held);
+                    }
                 }
                 Action::Mining => {
-                    dur[touch.unwrap()] -= deltat;
-                    if dur[touch.unwrap()] <= 0.0 {
-                        world.remove(touch.unwrap());
-                        dur.remove(touch.unwrap());
+                    // Progress restarts when the player looks at another block
+                    let id = voxels.get(hit.pos);
+                    let left = match mining {
+                        Some((pos, left)) if pos == hit.pos => left,
+                        _ => voxels
+                            .registry()
+                            .get(id)
+                            .map_or(0.0, |block| block.hardness),
+                    } - deltat;
+                    if left <= 0.0 {
+                        if let Some(drop) = voxels.registry().drop(id) {
+                            held = drop;
+                        }
+                        voxels.set(hit.pos, AIR);
+                        mining = None;
+                    } else {
+                        mining = Some((hit.pos, left));
                     }
-                    mining = true;
                 }
                 Action::No => (),
             }
-            for i in 0..dur.len() {
-                if i != touch.unwrap() || !mining {
-                    dur[i] = 1000.0;
-                }
-            }
         }
-        // Render all
+        if target.is_none() || !matches!(player.action, Action::Mining) {
+            mining = None;
+        }
+        voxels.update_meshes();
         for cube in world.iter_mut() {
             cube.update_physics(deltat);
-            cube.render(fb, &mut depth_buffer, &view_proj);
         }
         shark.update_physics(deltat);
-        shark.render(fb, &mut depth_buffer, &view_proj);
+        // Shadow pass around the player
+        if let Some(shadow) = lighting.begin_shadows(camera.eye()) {
+            for cube in voxels.objects().chain(world.iter()) {
+                cube.render_shadow(shadow);
+            }
+            shark.render_shadow(shadow);
+        }
+        // Render all
+        object::render_objects(
+            voxels.objects().chain(world.iter()).chain([&shark]),
+            &mut color_buffer,
+            &mut depth_buffer,
+            &view_proj,
+            &lighting,
+        );
+        color_buffer.resolve(fb, tone_mapping);
         // Draw cursor in the middle
         let screen_size = [fb.width(), fb.height()];
         for x in screen_size[0] / 2 - 2..screen_size[0] / 2 + 2 {
@@ -236,15 +320,27 @@ fn main() {
                 fb.set_pixel(x, y, rgb_to_u32(255, 0, 0));
             }
         }
-        deltat = (timer.elapsed().unwrap().as_millis() - start) as f32;
+        deltat = match fixed_deltat {
+            Some(fixed) => fixed,
+            None => (timer.elapsed().unwrap().as_millis() - start) as f32,
+        };
+        sim_time += deltat / 1000.0;
 
         //Benchmark
-        // println!("Matrix multiplications: {}", types::get_matrix_mul_count());
+        #[cfg(feature = "matrix-stats")]
+        {
+            println!("Matrix multiplications: {}", types::get_matrix_mul_count());
+            types::reset_matrix_mul_count();
+        }
+        #[cfg(feature = "cull-stats")]
+        {
+            println!("Culled objects: {}", object::get_culled_count());
+            object::reset_culled_count();
+        }
         // println!(
         //     "Time between frames: {}",
         //     timer.elapsed().unwrap().as_millis() - start
         // );
-        // types::reset_matrix_mul_count();
         window.update();
     }
 }
@@ -0,0 +1,272 @@
+use crate::{
+    model::{generate_tangents, Mesh, Model, Vertex},
+    voxel::{BlockId, VoxelWorld, AIR, CHUNK_SIZE},
+};
+
+// Side of a unit cube in rendered space: outward normal, corners counter-clockwise from outside
+// with their texture coordinates and the axes the coordinates run along
+struct Face {
+    normal: [f32; 3],
+    corners: [[f32; 3]; 4],
+    uvs: [[f32; 2]; 4],
+    u_axis: usize,
+    v_axis: usize,
+}
+// Same order as the tiles of the six tile strip and Mesh::cube
+pub const FACES: usize = 6;
+const FACE: [Face; FACES] = [
+    Face {
+        normal: [0.0, 0.0, 1.0],
+        corners: [
+            [-0.5, -0.5, 0.5],
+            [0.5, -0.5, 0.5],
+            [0.5, 0.5, 0.5],
+            [-0.5, 0.5, 0.5],
+        ],
+        uvs: [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        u_axis: 0,
+        v_axis: 1,
+    },
+    Face {
+        normal: [1.0, 0.0, 0.0],
+        corners: [
+            [0.5, -0.5, 0.5],
+            [0.5, -0.5, -0.5],
+            [0.5, 0.5, -0.5],
+            [0.5, 0.5, 0.5],
+        ],
+        uvs: [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        u_axis: 2,
+        v_axis: 1,
+    },
+    Face {
+        normal: [0.0, 0.0, -1.0],
+        corners: [
+            [0.5, -0.5, -0.5],
+            [-0.5, -0.5, -0.5],
+            [-0.5, 0.5, -0.5],
+            [0.5, 0.5, -0.5],
+        ],
+        uvs: [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        u_axis: 0,
+        v_axis: 1,
+    },
+    Face {
+        normal: [-1.0, 0.0, 0.0],
+        corners: [
+            [-0.5, -0.5, -0.5],
+            [-0.5, -0.5, 0.5],
+            [-0.5, 0.5, 0.5],
+            [-0.5, 0.5, -0.5],
+        ],
+        uvs: [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        u_axis: 2,
+        v_axis: 1,
+    },
+    // Top, rendered y grows downwards
+    Face {
+        normal: [0.0, -1.0, 0.0],
+        corners: [
+            [0.5, -0.5, -0.5],
+            [0.5, -0.5, 0.5],
+            [-0.5, -0.5, 0.5],
+            [-0.5, -0.5, -0.5],
+        ],
+        uvs: [[1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]],
+        u_axis: 0,
+        v_axis: 2,
+    },
+    Face {
+        normal: [0.0, 1.0, 0.0],
+        corners: [
+            [0.5, 0.5, 0.5],
+            [0.5, 0.5, -0.5],
+            [-0.5, 0.5, -0.5],
+            [-0.5, 0.5, 0.5],
+        ],
+        uvs: [[1.0, 1.0], [1.0, 0.0], [0.0, 0.0], [0.0, 1.0]],
+        u_axis: 0,
+        v_axis: 2,
+    },
+];
+
+// Builds the mesh of a chunk out of the faces that can be seen, those next to air or
+// transparent blocks, including neighbours in other chunks. When the world meshes greedily
+// neighbouring faces of the same block are merged into rectangles. Every face shows its
+// rectangle of the atlas, repeated once per block.
+pub fn mesh_chunk(world: &VoxelWorld, key: [i32; 3]) -> Model {
+    let origin = key.map(|c| c * CHUNK_SIZE as i32);
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (f, face) in FACE.iter().enumerate() {
+        let (u_axis, v_axis) = (face.u_axis, face.v_axis);
+        let axis = 3 - u_axis - v_axis;
+        // Rendered space is mirrored, the neighbour in block coordinates is against the normal
+        let behind: [i32; 3] = face.normal.map(|c| -c as i32);
+        for depth in 0..CHUNK_SIZE {
+            // Block of every visible face in the slice
+            let mut mask = [[None; CHUNK_SIZE]; CHUNK_SIZE];
+            for (u, row) in mask.iter_mut().enumerate() {
+                for (v, cell) in row.iter_mut().enumerate() {
+                    let mut pos = origin;
+                    pos[axis] += depth as i32;
+                    pos[u_axis] += u as i32;
+                    pos[v_axis] += v as i32;
+                    let id = world.get(pos);
+                    if id == AIR {
+                        continue;
+                    }
+                    let next = world.get(std::array::from_fn(|i| pos[i] + behind[i]));
+                    if is_see_through(world, next) {
+                        *cell = Some(id);
+                    }
+                }
+            }
+            // Cover the mask with rectangles, one block each without greedy meshing
+            for u in 0..CHUNK_SIZE {
+                for v in 0..CHUNK_SIZE {
+                    let Some(id) = mask[u][v] else {
+                        continue;
+                    };
+                    let (mut width, mut height) = (1, 1);
+                    if world.greedy() {
+                        while u + width < CHUNK_SIZE && mask[u + width][v] == Some(id) {
+                            width += 1;
+                        }
+                        while v + height < CHUNK_SIZE
+                            && (u..u + width).all(|u| mask[u][v + height] == Some(id))
+                        {
+                            height += 1;
+                        }
+                    }
+                    for row in &mut mask[u..u + width] {
+                        row[v..v + height].fill(None);
+                    }
+                    // First and last block of the rectangle
+                    let mut lo = origin;
+                    lo[axis] += depth as i32;
+                    lo[u_axis] += u as i32;
+                    lo[v_axis] += v as i32;
+                    let mut hi = lo;
+                    hi[u_axis] += width as i32 - 1;
+                    hi[v_axis] += height as i32 - 1;
+                    let mut size = [1.0; 3];
+                    size[u_axis] = width as f32;
+                    size[v_axis] = height as f32;
+
+                    let rect = world.face_rect(id, f);
+                    let base = vertices.len() as u32;
+                    for (corner, uv) in face.corners.iter().zip(face.uvs) {
+                        // Blocks sit at minus their coordinates
+                        let position = std::array::from_fn(|i| {
+                            if corner[i] < 0.0 {
+                                corner[i] - hi[i] as f32
+                            } else {
+                                corner[i] - lo[i] as f32
+                            }
+                        });
+                        vertices.push(Vertex {
+                            position,
+                            normal: face.normal,
+                            tex_coord: [uv[0] * size[u_axis], uv[1] * size[v_axis]],
+                            tile: Some(rect),
+                            ..Default::default()
+                        });
+                    }
+                    indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+                }
+            }
+        }
+    }
+    let mut meshes = Vec::new();
+    if !indices.is_empty() {
+        generate_tangents(&mut vertices, &indices);
+        meshes.push(Mesh::new(vertices, indices, 0));
+    }
+    Model::new(meshes, vec![world.material().clone()])
+}
+
+fn is_see_through(world: &VoxelWorld, id: BlockId) -> bool {
+    id == AIR || world.is_transparent(id)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BlockRegistry, BlockType, FaceTexture};
+    use crate::texture::Texture;
+    use crate::voxel::tests::world_of;
+    use std::collections::HashMap;
+
+    fn world(greedy: bool) -> VoxelWorld {
+        world_of(&["stone"]).0.with_greedy(greedy)
+    }
+
+    fn triangles(world: &VoxelWorld, key: [i32; 3]) -> usize {
+        let model = mesh_chunk(world, key);
+        model.meshes.iter().map(|mesh| mesh.indices.len() / 3).sum()
+    }
+
+    #[test]
+    fn hidden_faces_are_culled_and_merged() {
+        let stone = 1;
+        let [mut world, mut greedy] = [false, true].map(world);
+        for world in [&mut world, &mut greedy] {
+            world.set([0, 0, 0], stone);
+            assert_eq!(triangles(world, [0, 0, 0]), 12);
+            // The touching faces disappear
+            world.set([1, 0, 0], stone);
+        }
+        assert_eq!(triangles(&world, [0, 0, 0]), 20);
+        // The rest merge into one quad per side
+        assert_eq!(triangles(&greedy, [0, 0, 0]), 12);
+        // Neighbour in the next chunk hides the face on the border
+        greedy.set([-1, 0, 0], stone);
+        assert_eq!(triangles(&greedy, [0, 0, 0]), 10);
+    }
+
+    #[test]
+    fn border_edits_dirty_neighbours() {
+        let mut world = world(false);
+        let stone = 1;
+        world.set([0, 0, 0], stone);
+        world.set([-1, 0, 0], stone);
+        world.update_meshes();
+        world.set([-1, 0, 0], AIR);
+        world.update_meshes();
+        let model = &world.objects().next().unwrap().model;
+        let count: usize = model.meshes.iter().map(|m| m.indices.len() / 3).sum();
+        assert_eq!(count, 12);
+        // Every face is drawn with the atlas material
+        assert_eq!(model.meshes.len(), 1);
+    }
+
+    #[test]
+    fn faces_sample_their_atlas_rect() {
+        // Top, side and bottom show different tiles of the placeholder strip
+        let [top, side, bottom] = [0, 1, 2].map(|tile| FaceTexture::tile("placeholder", tile));
+        let log = BlockType::new("log", side.clone()).with_faces(top, side, bottom);
+        let images = HashMap::from([("placeholder".to_string(), Texture::placeholder())]);
+        let registry = BlockRegistry::new().with_block(log);
+        for greedy in [false, true] {
+            let mut world = VoxelWorld::from_images(registry.clone(), &images).with_greedy(greedy);
+            world.set([0, 0, 0], 1);
+            let model = mesh_chunk(&world, [0, 0, 0]);
+            let vertices = &model.meshes[0].vertices;
+            assert_eq!(vertices.len(), 4 * FACES);
+            for (f, face) in FACE.iter().enumerate() {
+                let rect = world.face_rect(1, f);
+                let corners: Vec<_> = vertices
+                    .iter()
+                    .filter(|v| v.normal == face.normal)
+                    .collect();
+                assert_eq!(corners.len(), 4);
+                for vertex in corners {
+                    assert_eq!(vertex.tile, Some(rect));
+                    assert!(vertex.tex_coord.iter().all(|c| (0.0..=1.0).contains(c)));
+                }
+            }
+            let [side, top, bottom] = [0, 4, 5].map(|f| world.face_rect(1, f));
+            assert!(side != top && side != bottom && top != bottom);
+        }
+    }
+}
@@ -1,9 +1,20 @@
+use crate::atlas::UvRect;
+use crate::bounds::Bounds;
+use crate::error::{Error, Result};
 use crate::texture::*;
+use crate::types::{Mat4, Vec3, Vec4};
+use gltf::mesh::Mode;
+use gltf::Semantic;
+use std::sync::Arc;
 #[derive(Clone, Copy, Debug)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub tex_coord: [f32; 2],
+    // Direction of growing u with the bitangent sign in w, as in glTF
+    pub tangent: [f32; 4],
+    // Rectangle of the texture the coordinate repeats inside, for tiles of an atlas
+    pub tile: Option<UvRect>,
 }
 
 impl Default for Vertex {
@@ -12,6 +23,8 @@ impl Default for Vertex {
             position: [0.0; 3],
             normal: [0.0; 3],
             tex_coord: [0.0; 2],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            tile: None,
         }
     }
 }
@@ -20,11 +33,55 @@ pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub material_idx: usize,
+    // In model space, recompute after changing vertices
+    pub bounds: Bounds,
 }
 #[derive(Clone, Debug)]
 pub struct Material {
     pub base_col: [f32; 4],
-    pub base_color_texture: Option<Texture>,
+    // Shared, so materials using the same image don't keep their own copy
+    pub base_color_texture: Option<SampledTexture>,
+    pub shading: Shading,
+    // Blinn-Phong highlight strength and exponent
+    pub specular: f32,
+    pub shininess: f32,
+    // Metallic-roughness factors, multiplied by blue and green of the texture
+    pub metallic: f32,
+    pub roughness: f32,
+    pub metallic_roughness_texture: Option<SampledTexture>,
+    // Tangent space normal map, scale multiplies its x and y
+    pub normal_texture: Option<SampledTexture>,
+    pub normal_scale: f32,
+    // Light given off by the surface, added after lighting
+    pub emissive: [f32; 3],
+    pub emissive_texture: Option<SampledTexture>,
+    // Red channel darkens ambient light, strength 0 turns it off
+    pub occlusion_texture: Option<SampledTexture>,
+    pub occlusion_strength: f32,
+    // Both sides are drawn, the back one with flipped normals
+    pub double_sided: bool,
+    pub alpha_mode: AlphaMode,
+    // Masked pixels with lower alpha are discarded
+    pub alpha_cutoff: f32,
+}
+// How alpha of the base color is used, as in glTF
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    // Alpha is ignored
+    #[default]
+    Opaque,
+    // Pixels are either kept or discarded by alpha_cutoff
+    Mask,
+    // Blended over what is behind, drawn after opaque geometry from back to front
+    Blend,
+}
+// Lighting model used for a material
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shading {
+    #[default]
+    BlinnPhong,
+    // Cook-Torrance, used for glTF materials
+    MetallicRoughness,
 }
 
 impl Default for Material {
@@ -32,6 +89,21 @@ impl Default for Material {
         Material {
             base_col: [1.0; 4],
             base_color_texture: None,
+            shading: Shading::BlinnPhong,
+            specular: 0.2,
+            shininess: 32.0,
+            metallic: 0.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            emissive: [0.0; 3],
+            emissive_texture: None,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            double_sided: false,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
         }
     }
 }
@@ -39,112 +111,655 @@ impl Default for Material {
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub mats: Vec<Material>,
+    // Union of the mesh bounds
+    pub bounds: Bounds,
+}
+
+impl Mesh {
+    // Cube with center of 0,0,0 and edge lenght of 1. Texture coordinates expect a strip of six
+    // square faces next to each other.
+    pub fn cube() -> Mesh {
+        let mut vertices: Vec<Vertex> = Vec::new();
+
+        // Sides
+        for i in 0..4 {
+            // println!("{}", i);
+            for j in 0..4 {
+                let position = if (i % 2) == 1 {
+                    [
+                        0.5 - ((i) % 4 > 1) as i32 as f32,
+                        -0.5 + (j % 4 > 1) as i32 as f32,
+                        -0.5 + ((i + j + 2) % 4 > 1) as i32 as f32,
+                    ]
+                } else {
+                    [
+                        -0.5 + ((j + i + 1) % 4 > 1) as i32 as f32,
+                        -0.5 + (j % 4 > 1) as i32 as f32,
+                        0.5 - ((i) % 4 > 1) as i32 as f32,
+                    ]
+                };
+                let normal = [
+                    ((i) % 2) as f32 * (2.0 - i as f32),
+                    0.0,
+                    ((i + 1) % 2) as f32 * (1.0 - i as f32),
+                ];
+                let tex_coord = [
+                    ((i + ((j + 1) % 4 > 1) as i32) as f32) / 6.0,
+                    position[1] + 0.5,
+                ];
+                // println!("{:?} {:?}", position, tex_coord);
+
+                vertices.push(Vertex {
+                    normal,
+                    position,
+                    tex_coord,
+                    ..Default::default()
+                });
+            }
+        }
+        // Top
+        for i in 0..4 {
+            let position = [
+                0.5 - (i % 4 > 1) as i32 as f32,
+                -0.5,
+                0.5 - ((i + 3) % 4 > 1) as i32 as f32,
+            ];
+            let tex_coord = [(4.0 + position[0] + 0.5) / 6.0, position[2] + 0.5];
+
+            vertices.push(Vertex {
+                normal: [0.0, -1.0, 0.0],
+                position,
+                tex_coord,
+                ..Default::default()
+            });
+        }
+        // Bottom
+        for i in 0..4 {
+            let position = [
+                0.5 - (i % 4 > 1) as i32 as f32,
+                0.5,
+                0.5 - ((i + 1) % 4 > 1) as i32 as f32,
+            ];
+            let tex_coord = [(5.0 + position[0] + 0.5) / 6.0, position[2] + 0.5];
+
+            vertices.push(Vertex {
+                normal: [0.0, 1.0, 0.0],
+                position,
+                tex_coord,
+                ..Default::default()
+            });
+        }
+        // println!("{}", vertices.len());
+
+        let mut indices: Vec<u32> = Vec::new();
+        for i in 0..6 {
+            indices.append(&mut vec![i * 4, i * 4 + 1, (i * 4) + 2]);
+            indices.append(&mut vec![i * 4, i * 4 + 2, i * 4 + 3]);
+        }
+        // println!("{:?}", indices);
+        generate_tangents(&mut vertices, &indices);
+
+        Mesh::new(vertices, indices, 0)
+    }
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, material_idx: usize) -> Mesh {
+        let bounds = Bounds::from_points(vertices.iter().map(|v| v.position));
+        Mesh {
+            vertices,
+            indices,
+            material_idx,
+            bounds,
+        }
+    }
+}
+impl Model {
+    pub fn new(meshes: Vec<Mesh>, mats: Vec<Material>) -> Model {
+        let mut model = Model {
+            meshes,
+            mats,
+            bounds: Bounds::default(),
+        };
+        model.update_bounds();
+        model
+    }
+    pub fn update_bounds(&mut self) {
+        self.bounds = match self.meshes.split_first() {
+            Some((first, rest)) => rest
+                .iter()
+                .fold(first.bounds, |bounds, mesh| bounds.union(&mesh.bounds)),
+            None => Bounds::default(),
+        };
+    }
+    // Checkerboard cube shown in place of models that failed to load
+    pub fn placeholder() -> Model {
+        let mat = Material {
+            base_color_texture: Some(
+                SampledTexture::new(Arc::new(Texture::placeholder()))
+                    .with_sampler(Sampler::pixelated()),
+            ),
+            ..Default::default()
+        };
+        Model::new(vec![Mesh::cube()], vec![mat])
+    }
 }
 
 pub fn load_model(path: &str) -> Model {
-    let (doc, buf, _img) = gltf::import(path).expect("failed to load model");
+    try_load_model(path).unwrap_or_else(|err| panic!("{err}"))
+}
+// Loads model, on failure reports the error and returns the placeholder cube
+pub fn load_model_or_placeholder(path: &str) -> Model {
+    try_load_model(path).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        Model::placeholder()
+    })
+}
+pub fn try_load_model(path: &str) -> Result<Model> {
+    let (doc, buf, images) = gltf::import(path).map_err(|err| Error::from_gltf(path, err))?;
     let mut meshes: Vec<Mesh> = Vec::new();
-    let mut mats: Vec<Material> = vec![Material::default(); doc.materials().len()];
-    if mats.is_empty() {
-        mats.push(Material::default());
-    }
 
-    if doc.nodes().len() > 0 {
+    // gltf::import already decoded every image, whether it's a file, data URI or buffer view.
+    // They are converted lazily, once per image and color space, and shared by all materials.
+    let mut textures: Vec<[Option<Arc<Texture>>; 2]> = vec![[None, None]; images.len()];
+    let mut mats: Vec<Material> = doc
+        .materials()
+        .map(|mat| load_material(&mat, &images, &mut textures, path))
+        .collect::<Result<_>>()?;
+    let default_mat = mats.len();
+    mats.push(Material::default());
+
+    // Walk the default scene, files without scenes get all their root nodes drawn
+    let roots: Vec<gltf::Node> = match doc.default_scene().or_else(|| doc.scenes().next()) {
+        Some(scene) => scene.nodes().collect(),
+        None => {
+            let children: Vec<usize> = doc
+                .nodes()
+                .flat_map(|node| node.children().map(|child| child.index()))
+                .collect();
+            doc.nodes()
+                .filter(|node| !children.contains(&node.index()))
+                .collect()
+        }
+    };
+    for node in roots.iter() {
         process_node(
-            doc.nodes().next().as_ref().unwrap(),
+            node,
+            &Mat4::identity(),
             &buf,
             &mut meshes,
-            &mut mats,
+            default_mat,
             path,
-        );
+        )?;
     }
 
-    Model { meshes, mats }
+    Ok(Model::new(meshes, mats))
+}
+
+// Per-vertex tangents from positions and texture coordinates, summed over the triangles sharing
+// the vertex and made orthogonal to the normal. glTF texture coordinates grow downwards, so the
+// bitangent cross(normal, tangent) * w points towards decreasing v.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vec3::default(); vertices.len()];
+    let mut bitangents = vec![Vec3::default(); vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0] as usize, tri[1] as usize, tri[2] as usize];
+        let e1 = Vec3(vertices[b].position) - Vec3(vertices[a].position);
+        let e2 = Vec3(vertices[c].position) - Vec3(vertices[a].position);
+        let (t0, t1, t2) = (
+            vertices[a].tex_coord,
+            vertices[b].tex_coord,
+            vertices[c].tex_coord,
+        );
+        let (du1, dv1) = (t1[0] - t0[0], t1[1] - t0[1]);
+        let (du2, dv2) = (t2[0] - t0[0], t2[1] - t0[1]);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < 1e-12 {
+            continue;
+        }
+        let tangent = (e1 * dv2 - e2 * dv1) * (1.0 / det);
+        let bitangent = (e2 * du1 - e1 * du2) * (1.0 / det);
+        for i in [a, b, c] {
+            tangents[i] = tangents[i] + tangent;
+            bitangents[i] = bitangents[i] + bitangent;
+        }
+    }
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = Vec3(vertex.normal);
+        let tangent = (tangents[i] - normal * normal.dot(&tangents[i])).normalize();
+        if tangent.length() == 0.0 {
+            continue;
+        }
+        let w = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 {
+            1.0
+        } else {
+            -1.0
+        };
+        vertex.tangent = [tangent[0], tangent[1], tangent[2], w];
+    }
 }
 
+// Loads meshes of the node and its children with node transforms baked into the vertices
 fn process_node(
     node: &gltf::Node,
+    parent_transform: &Mat4,
     buffers: &[gltf::buffer::Data],
     meshes: &mut Vec<Mesh>,
-    mats: &mut [Material],
+    default_mat: usize,
     path: &str,
-) {
+) -> Result<()> {
+    // glTF matrices are column-major, which is exactly our row-vector layout
+    let transform = *parent_transform * Mat4(node.transform().matrix());
+    let normal_matrix = transform.normal_matrix();
+    // Mirroring transforms turn the triangles inside out
+    let flip_winding = transform.det() < 0.0;
+
     if let Some(mesh) = node.mesh() {
         for prim in mesh.primitives() {
-            if prim.mode() == gltf::mesh::Mode::Triangles {
-                let reader = prim.reader(|buffer| Some(&buffers[buffer.index()]));
-                let positions = {
-                    let iter = reader
-                        .read_positions()
-                        .expect("Failed to process mesh node. (Vertices must have position)");
-                    iter.collect::<Vec<[f32; 3]>>()
-                };
+            let reader = prim.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions = reader
+                .read_positions()
+                .ok_or(Error::MissingAttribute {
+                    path: path.to_string(),
+                    attribute: "POSITION",
+                })?
+                .collect::<Vec<[f32; 3]>>();
 
-                let mut vertices: Vec<Vertex> = positions
-                    .into_iter()
-                    .map(|position| Vertex {
-                        position,
-                        ..Default::default()
-                    })
-                    .collect();
+            // Other attributes are written per vertex, so they must not have more elements
+            let attributes = [
+                (Semantic::Normals, "NORMAL"),
+                (Semantic::TexCoords(0), "TEXCOORD_0"),
+                (Semantic::Tangents, "TANGENT"),
+            ];
+            for (semantic, attribute) in attributes {
+                if prim
+                    .get(&semantic)
+                    .is_some_and(|accessor| accessor.count() != positions.len())
+                {
+                    return Err(Error::AttributeCountMismatch {
+                        path: path.to_string(),
+                        attribute,
+                    });
+                }
+            }
+
+            let mut vertices: Vec<Vertex> = positions
+                .into_iter()
+                .map(|position| Vertex {
+                    position,
+                    ..Default::default()
+                })
+                .collect();
 
-                if let Some(normals) = reader.read_normals() {
-                    for (i, normal) in normals.enumerate() {
-                        vertices[i].normal = normal;
-                    }
+            if let Some(normals) = reader.read_normals() {
+                for (i, normal) in normals.enumerate() {
+                    vertices[i].normal = normal;
                 }
-                if let Some(tex_coords) = reader.read_tex_coords(0) {
-                    for (i, tex_coord) in tex_coords.into_f32().enumerate() {
-                        vertices[i].tex_coord = tex_coord;
-                    }
+            }
+            if let Some(tex_coords) = reader.read_tex_coords(0) {
+                for (i, tex_coord) in tex_coords.into_f32().enumerate() {
+                    vertices[i].tex_coord = tex_coord;
                 }
-
-                let indices = reader
-                    .read_indices()
-                    .map(|read_indices| read_indices.into_u32().collect::<Vec<_>>())
-                    .expect("Failed to read indices");
-
-                let prim_mat = prim.material();
-                let pbr = prim_mat.pbr_metallic_roughness();
-                let material_idx = prim_mat.index().unwrap_or(0);
-
-                let mat = &mut mats[material_idx];
-                mat.base_col = pbr.base_color_factor();
-                if let Some(base_color_texture) = pbr.base_color_texture() {
-                    let sauce = base_color_texture.texture().source().source();
-                    match sauce {
-                        gltf::image::Source::Uri { uri, .. } => {
-                            let mpath = std::path::Path::new(path);
-                            let tpath = mpath
-                                .parent()
-                                .unwrap_or_else(|| std::path::Path::new("./"))
-                                .join(uri);
-                            let tpaths = tpath.into_os_string().into_string().unwrap();
-                            mat.base_color_texture = Some(load_texture(&tpaths));
-                        }
-                        gltf::image::Source::View { view, .. } => {
-                            let start = view.offset() as usize;
-                            let end = start + view.length() as usize;
-                            let data = buffers.get(0).unwrap().to_vec();
-                            let img = image::load_from_memory(&data[start..end]).unwrap();
-
-                            let texture = Texture {
-                                channels: img.color().bytes_per_pixel() as usize,
-                                width: img.width(),
-                                height: img.height(),
-                                data: img.into_bytes(),
-                            };
-                            mat.base_color_texture = Some(texture);
-                        }
-                    }
+            }
+            let tangents = reader.read_tangents();
+            let has_tangents = tangents.is_some();
+            if let Some(tangents) = tangents {
+                for (i, tangent) in tangents.enumerate() {
+                    vertices[i].tangent = tangent;
                 }
+            }
 
-                meshes.push(Mesh {
-                    vertices,
-                    indices,
-                    material_idx,
+            // Non-indexed primitives use every vertex once in order
+            let indices = match reader.read_indices() {
+                Some(read_indices) => read_indices.into_u32().collect::<Vec<_>>(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+            let mut indices = match prim.mode() {
+                Mode::Triangles => indices,
+                Mode::TriangleStrip => strip_to_list(&indices),
+                Mode::TriangleFan => fan_to_list(&indices),
+                mode => {
+                    return Err(Error::UnsupportedPrimitiveMode {
+                        path: path.to_string(),
+                        mode,
+                    })
+                }
+            };
+            if let Some(&index) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+                return Err(Error::InvalidIndex {
+                    path: path.to_string(),
+                    index,
+                    vertex_count: vertices.len(),
                 });
             }
+            if !has_tangents {
+                generate_tangents(&mut vertices, &indices);
+            }
+
+            for vertex in vertices.iter_mut() {
+                vertex.position = (&transform * Vec3(vertex.position)).0;
+                vertex.normal = (&normal_matrix * Vec3(vertex.normal)).normalize().0;
+                let [x, y, z, w] = vertex.tangent;
+                let tangent = (&transform * Vec4::dir([x, y, z])).xyz().normalize();
+                // Mirroring also mirrors the bitangent given by cross(normal, tangent)
+                let w = if flip_winding { -w } else { w };
+                vertex.tangent = [tangent[0], tangent[1], tangent[2], w];
+            }
+            if flip_winding {
+                for tri in indices.chunks_exact_mut(3) {
+                    tri.swap(1, 2);
+                }
+            }
+
+            // Primitives without material use the default one at the end
+            let material_idx = prim.material().index().unwrap_or(default_mat);
+
+            // Accessor min and max of the positions, moved along with the vertices
+            let bb = prim.bounding_box();
+            meshes.push(Mesh {
+                vertices,
+                indices,
+                material_idx,
+                bounds: Bounds::new(bb.min, bb.max).transform(&transform),
+            });
+        }
+    }
+    for child in node.children() {
+        process_node(&child, &transform, buffers, meshes, default_mat, path)?;
+    }
+    Ok(())
+}
+
+// Every triangle of a strip after the first flips winding, so odd ones are swapped back
+fn strip_to_list(indices: &[u32]) -> Vec<u32> {
+    let mut list = Vec::new();
+    for (i, w) in indices.windows(3).enumerate() {
+        if i % 2 == 0 {
+            list.extend_from_slice(&[w[0], w[1], w[2]]);
+        } else {
+            list.extend_from_slice(&[w[1], w[0], w[2]]);
+        }
+    }
+    list
+}
+fn fan_to_list(indices: &[u32]) -> Vec<u32> {
+    let mut list = Vec::new();
+    for w in indices.windows(2).skip(1) {
+        list.extend_from_slice(&[indices[0], w[0], w[1]]);
+    }
+    list
+}
+
+fn load_material(
+    mat: &gltf::Material,
+    images: &[gltf::image::Data],
+    textures: &mut [[Option<Arc<Texture>>; 2]],
+    path: &str,
+) -> Result<Material> {
+    let pbr = mat.pbr_metallic_roughness();
+    let mut slot = |info: Option<gltf::Texture>, srgb: bool| {
+        info.map(|texture| load_texture_slot(&texture, images, textures, srgb, path))
+            .transpose()
+    };
+    Ok(Material {
+        base_col: pbr.base_color_factor(),
+        base_color_texture: slot(pbr.base_color_texture().map(|info| info.texture()), true)?,
+        shading: Shading::MetallicRoughness,
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        metallic_roughness_texture: slot(
+            pbr.metallic_roughness_texture().map(|info| info.texture()),
+            false,
+        )?,
+        normal_texture: slot(mat.normal_texture().map(|info| info.texture()), false)?,
+        normal_scale: mat.normal_texture().map_or(1.0, |info| info.scale()),
+        emissive: mat.emissive_factor(),
+        emissive_texture: slot(mat.emissive_texture().map(|info| info.texture()), true)?,
+        occlusion_texture: slot(mat.occlusion_texture().map(|info| info.texture()), false)?,
+        occlusion_strength: mat.occlusion_texture().map_or(1.0, |info| info.strength()),
+        double_sided: mat.double_sided(),
+        alpha_mode: match mat.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: mat.alpha_cutoff().unwrap_or(0.5),
+        ..Default::default()
+    })
+}
+
+// Image of the glTF texture, decoded once per image, with the sampler of this texture. Color
+// slots are sRGB, data slots (normals, metallic-roughness, occlusion) linear.
+fn load_texture_slot(
+    texture: &gltf::Texture,
+    images: &[gltf::image::Data],
+    textures: &mut [[Option<Arc<Texture>>; 2]],
+    srgb: bool,
+    path: &str,
+) -> Result<SampledTexture> {
+    let index = texture.source().index();
+    let image = match &mut textures[index][srgb as usize] {
+        Some(image) => image.clone(),
+        slot => slot
+            .insert(Arc::new(Texture::from_gltf(&images[index], srgb, path)?))
+            .clone(),
+    };
+    Ok(SampledTexture::new(image).with_sampler(Sampler::from_gltf(&texture.sampler())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("model_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Writes a glTF file with one triangle drawn by the given primitive
+    fn write_gltf(name: &str, primitive: &str) -> PathBuf {
+        let scene = format!(
+            r#""meshes": [{{"primitives": [{primitive}]}}],
+  "nodes": [{{"mesh": 0}}],
+  "scenes": [{{"nodes": [0]}}]"#
+        );
+        write_gltf_with(name, &scene)
+    }
+
+    // Writes a glTF file of the given meshes, nodes and the rest. Accessors are the 3
+    // positions, 4 normals, indices 0 1 2, indices 0 1 5 and positions whose data is cut off.
+    fn write_gltf_with(name: &str, properties: &str) -> PathBuf {
+        let dir = dir();
+        let mut bin = Vec::new();
+        let floats = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .into_iter()
+            .chain([[0.0, 0.0, 1.0f32]; 4]);
+        for value in floats.flatten() {
+            bin.extend(value.to_le_bytes());
+        }
+        for index in [0, 1, 2, 0, 1, 5u16] {
+            bin.extend(index.to_le_bytes());
         }
+        std::fs::write(dir.join(format!("{name}.bin")), &bin).unwrap();
+        let gltf = format!(
+            r#"{{
+  "asset": {{"version": "2.0"}},
+  "buffers": [{{"uri": "{name}.bin", "byteLength": 96}}],
+  "bufferViews": [
+    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+    {{"buffer": 0, "byteOffset": 36, "byteLength": 48}},
+    {{"buffer": 0, "byteOffset": 84, "byteLength": 6}},
+    {{"buffer": 0, "byteOffset": 90, "byteLength": 6}}
+  ],
+  "accessors": [
+    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+      "min": [0, 0, 0], "max": [1, 1, 0]}},
+    {{"bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC3"}},
+    {{"bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR"}},
+    {{"bufferView": 3, "componentType": 5123, "count": 3, "type": "SCALAR"}},
+    {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [0, 0, 0]}}
+  ],
+  {properties}
+}}"#
+        );
+        let path = dir.join(format!("{name}.gltf"));
+        std::fs::write(&path, gltf).unwrap();
+        path
+    }
+
+    fn load(name: &str, primitive: &str) -> Result<Model> {
+        try_load_model(write_gltf(name, primitive).to_str().unwrap())
+    }
+
+    #[test]
+    fn loads_triangle() {
+        let model = load(
+            "triangle",
+            r#"{"attributes": {"POSITION": 0}, "indices": 2}"#,
+        )
+        .unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn bakes_node_transforms() {
+        // Translated parent with a scaled child, and a mirrored root
+        let scene = r#""meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "indices": 2}]}],
+  "nodes": [
+    {"translation": [10, 0, 0], "children": [1]},
+    {"scale": [2, 2, 2], "mesh": 0},
+    {"scale": [-1, 1, 1], "mesh": 0}
+  ],
+  "scenes": [{"nodes": [0, 2]}]"#;
+        let path = write_gltf_with("nodes", scene);
+        let model = try_load_model(path.to_str().unwrap()).unwrap();
+        assert_eq!(model.meshes.len(), 2);
+        let positions = |mesh: &Mesh| mesh.vertices.iter().map(|v| v.position).collect::<Vec<_>>();
+
+        let child = &model.meshes[0];
+        let expected = [[10.0, 0.0, 0.0], [12.0, 0.0, 0.0], [10.0, 2.0, 0.0]];
+        assert_eq!(positions(child), expected);
+        assert_eq!(child.indices, [0, 1, 2]);
+
+        // Mirroring flips the winding back to counter-clockwise
+        let mirrored = &model.meshes[1];
+        let expected = [[0.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        assert_eq!(positions(mirrored), expected);
+        assert_eq!(mirrored.indices, [0, 2, 1]);
+    }
+
+    #[test]
+    fn materials_share_decoded_images() {
+        image::RgbaImage::new(2, 2)
+            .save(dir().join("shared.png"))
+            .unwrap();
+        // Two textures of the same image, used as color by two materials and as normals by one
+        let scene = r#""images": [{"uri": "shared.png"}],
+  "textures": [{"source": 0}, {"source": 0}],
+  "materials": [
+    {"pbrMetallicRoughness": {"baseColorTexture": {"index": 0}}},
+    {"pbrMetallicRoughness": {"baseColorTexture": {"index": 1}}, "normalTexture": {"index": 0}}
+  ]"#;
+        let path = write_gltf_with("shared", scene);
+        let model = try_load_model(path.to_str().unwrap()).unwrap();
+        let texture = |slot: &Option<SampledTexture>| slot.as_ref().unwrap().texture.clone();
+        let first = texture(&model.mats[0].base_color_texture);
+        let second = texture(&model.mats[1].base_color_texture);
+        let normal = texture(&model.mats[1].normal_texture);
+        assert!(Arc::ptr_eq(&first, &second));
+        // Linear copy is decoded separately
+        assert!(!Arc::ptr_eq(&first, &normal));
+        assert!(matches!(first.levels[0].texels, Texels::Srgba8(_)));
+        assert!(matches!(normal.levels[0].texels, Texels::Rgba8(_)));
+    }
+
+    // Unit quad facing +z with the given texture coordinates of its corners
+    fn quad_tangents(tex_coords: [[f32; 2]; 4]) -> Vec<[f32; 4]> {
+        let corners = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let mut vertices: Vec<Vertex> = corners
+            .iter()
+            .zip(tex_coords)
+            .map(|(&[x, y], tex_coord)| Vertex {
+                position: [x, y, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord,
+                ..Default::default()
+            })
+            .collect();
+        generate_tangents(&mut vertices, &[0, 1, 2, 0, 2, 3]);
+        vertices.iter().map(|v| v.tangent).collect()
+    }
+
+    #[test]
+    fn tangents_follow_u_and_bitangent_sign() {
+        // Image upright on the quad, v grows downwards
+        let tangents = quad_tangents([[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]]);
+        for tangent in tangents {
+            assert_eq!(tangent, [1.0, 0.0, 0.0, 1.0]);
+            // cross(normal, tangent) * w points up, towards decreasing v
+            let [x, y, z, w] = tangent;
+            let bitangent = Vec3([0.0, 0.0, 1.0]).cross(&Vec3([x, y, z])) * w;
+            assert_eq!(bitangent.0, [0.0, 1.0, 0.0]);
+        }
+        // Mirrored horizontally, u grows to the left and the bitangent keeps pointing up
+        let tangents = quad_tangents([[1.0, 1.0], [0.0, 1.0], [0.0, 0.0], [1.0, 0.0]]);
+        for tangent in tangents {
+            assert_eq!(tangent, [-1.0, 0.0, 0.0, -1.0]);
+            let [x, y, z, w] = tangent;
+            let bitangent = Vec3([0.0, 0.0, 1.0]).cross(&Vec3([x, y, z])) * w;
+            assert_eq!(bitangent.0, [0.0, 1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn reports_loader_errors() {
+        let err = try_load_model("missing/model.gltf").unwrap_err();
+        assert!(matches!(err, Error::Io { .. }), "{err}");
+
+        let points = r#"{"attributes": {"POSITION": 0}, "mode": 0}"#;
+        let err = load("points", points).unwrap_err();
+        assert!(
+            matches!(err, Error::UnsupportedPrimitiveMode { .. }),
+            "{err}"
+        );
+
+        let err = load("no_position", r#"{"attributes": {"POSITION": 4}}"#).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::MissingAttribute {
+                    attribute: "POSITION",
+                    ..
+                }
+            ),
+            "{err}"
+        );
+
+        let indices = r#"{"attributes": {"POSITION": 0}, "indices": 3}"#;
+        let err = load("bad_index", indices).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::InvalidIndex {
+                    index: 5,
+                    vertex_count: 3,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+
+        let normals = r#"{"attributes": {"POSITION": 0, "NORMAL": 1}, "indices": 2}"#;
+        let err = load("extra_normals", normals).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::AttributeCountMismatch {
+                    attribute: "NORMAL",
+                    ..
+                }
+            ),
+            "{err}"
+        );
     }
 }
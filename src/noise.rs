@@ -0,0 +1,151 @@
+// Seeded gradient noise for generating terrain
+
+// Small fast generator, the same seed always gives the same numbers
+#[derive(Clone, Debug)]
+pub struct SplitMix64(u64);
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    // Uniform in 0..1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Uniform 0..1 value of a lattice point, for decisions like tree placement that have to be the
+// same no matter which part of the world is generated first
+pub fn hash2(seed: u64, x: i32, z: i32) -> f32 {
+    let key = ((x as u32 as u64) << 32) | z as u32 as u64;
+    SplitMix64::new(seed ^ key.wrapping_mul(0xd6e8_feb8_6659_fd93)).next_f32()
+}
+
+// Classic Perlin noise with a permutation shuffled by the seed. Values are roughly in -1..1 and
+// zero on integer coordinates.
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    // Shuffled 0..256, hashes lattice coordinates
+    perm: [u8; 256],
+}
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut perm: [u8; 256] = std::array::from_fn(|i| i as u8);
+        let mut rng = SplitMix64::new(seed);
+        for i in (1..256).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            perm.swap(i, j);
+        }
+        Perlin { perm }
+    }
+    fn hash(&self, x: i32, y: i32, z: i32) -> u8 {
+        let p = |i: i32| self.perm[(i & 255) as usize] as i32;
+        p(p(p(x) + y) + z) as u8
+    }
+    pub fn noise2(&self, x: f32, y: f32) -> f32 {
+        self.noise3(x, y, 0.0)
+    }
+    pub fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        // Dot product of the corner's gradient with the offset from it
+        let corner = |i: i32, j: i32, k: i32| {
+            let h = self.hash(x0 + i, y0 + j, z0 + k);
+            grad(h, fx - i as f32, fy - j as f32, fz - k as f32)
+        };
+        let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+            ),
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+            ),
+        )
+    }
+    // Sum of octaves, each one with double the frequency and half the amplitude, normalized
+    // back to about -1..1
+    pub fn fbm2(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        self.fbm(octaves, |f, o| self.noise3(x * f, y * f, o))
+    }
+    pub fn fbm3(&self, x: f32, y: f32, z: f32, octaves: u32) -> f32 {
+        self.fbm(octaves, |f, o| self.noise3(x * f + o, y * f, z * f))
+    }
+    // Octaves are shifted apart, so their zeros at integer coordinates don't line up
+    fn fbm(&self, octaves: u32, noise: impl Fn(f32, f32) -> f32) -> f32 {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let (mut freq, mut amp) = (1.0, 1.0);
+        for octave in 0..octaves {
+            sum += amp * noise(freq, octave as f32 * 17.31);
+            total += amp;
+            freq *= 2.0;
+            amp *= 0.5;
+        }
+        sum / total
+    }
+}
+
+// Smoothstep with zero first and second derivatives at the ends
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+// One of the twelve cube edge directions picked by the hash
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = match h {
+        0..=3 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_seeded_and_bounded() {
+        let (a, b) = (Perlin::new(1), Perlin::new(2));
+        let points = (0..200).map(|i| {
+            let i = i as f32;
+            (i * 0.37 - 20.0, i * 0.11 + 3.3, i * -0.23)
+        });
+        let mut differs = false;
+        for (x, y, z) in points {
+            let value = a.noise3(x, y, z);
+            assert!(value.abs() <= 1.1);
+            assert_eq!(value, Perlin::new(1).noise3(x, y, z));
+            differs |= value != b.noise3(x, y, z);
+            assert!(a.fbm2(x, z, 4).abs() <= 1.1);
+        }
+        assert!(differs);
+        assert_eq!(a.noise3(3.0, -7.0, 12.0), 0.0);
+    }
+
+    #[test]
+    fn hash_is_uniform() {
+        let values: Vec<f32> = (-50..50)
+            .flat_map(|x| (-50..50).map(move |z| hash2(7, x, z)))
+            .collect();
+        assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        assert!((mean - 0.5).abs() < 0.02);
+        assert_ne!(hash2(7, 1, 2), hash2(7, 2, 1));
+        assert_ne!(hash2(7, 1, 2), hash2(8, 1, 2));
+    }
+}
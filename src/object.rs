@@ -1,12 +1,29 @@
 use crate::{
+    bounds::Frustum,
+    color::ColorBuffer,
+    depth::DepthBuffer,
     draw::*,
-    model::{Material, Mesh, Model, Vertex},
+    light::Lighting,
+    model::{AlphaMode, Material, Mesh, Model, Vertex},
     physics::Physics,
-    texture::{load_texture, Texture},
-    types::Matrix,
-    window::Framebuffer,
+    shadow::ShadowMap,
+    texture::{load_texture_or_placeholder, SampledTexture, Sampler},
+    types::{Mat3, Mat4, Vec3, Vec4},
 };
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+// Objects skipped by Object::render for being outside of the view, for debugging
+static CULLED_OBJECTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn get_culled_count() -> usize {
+    CULLED_OBJECTS.load(Ordering::Relaxed)
+}
+pub fn reset_culled_count() {
+    CULLED_OBJECTS.store(0, Ordering::Relaxed);
+}
 
 pub struct Object {
     pub model: Model,
@@ -26,112 +43,30 @@ impl Object {
         }
     }
     pub fn with_physics(mut self, physics: Arc<Mutex<Physics>>) -> Self {
-        let mut physics = physics;
         physics.lock().unwrap().mass_center = self.position;
         self.physics = Some(physics);
         self
     }
     // Creates cube with center of 0,0,0 and edge lenght of 1, that can be shifted, rotated and
-    // resized by params
+    // resized by params. Missing texture is reported and replaced by a checkerboard, textures
+    // with transparent texels (like leaves) are cut out.
     pub fn cube(
         position: [f32; 3],
         rotation: [f32; 3],
         scale: [f32; 3],
         texture_path: Option<&str>,
     ) -> Self {
-        let mut vertices: Vec<Vertex> = Vec::new();
-
-        // Sides
-        for i in 0..4 {
-            // println!("{}", i);
-            for j in 0..4 {
-                let mut position = [0.0; 3];
-                if (i % 2) == 1 {
-                    position = [
-                        0.5 - ((i) % 4 > 1) as i32 as f32,
-                        -0.5 + (j % 4 > 1) as i32 as f32,
-                        -0.5 + ((i + j + 2) % 4 > 1) as i32 as f32,
-                    ];
-                } else {
-                    position = [
-                        -0.5 + ((j + i + 1) % 4 > 1) as i32 as f32,
-                        -0.5 + (j % 4 > 1) as i32 as f32,
-                        0.5 - ((i) % 4 > 1) as i32 as f32,
-                    ];
-                }
-                let normal = [
-                    ((i) % 2) as f32 * (2.0 - i as f32),
-                    0.0,
-                    ((i + 1) % 2) as f32 * (1.0 - i as f32),
-                ];
-                let tex_coord = [
-                    ((i + ((j + 1) % 4 > 1) as i32) as f32) / 6.0,
-                    position[1] + 0.5,
-                ];
-                // println!("{:?} {:?}", position, tex_coord);
-
-                vertices.push(Vertex {
-                    normal,
-                    position,
-                    tex_coord,
-                });
-            }
-        }
-        // Top
-        for i in 0..4 {
-            let position = [
-                0.5 - (i % 4 > 1) as i32 as f32,
-                -0.5,
-                0.5 - ((i + 3) % 4 > 1) as i32 as f32,
-            ];
-            let tex_coord = [(4.0 + position[0] + 0.5) / 6.0, position[2] + 0.5];
-
-            vertices.push(Vertex {
-                normal: [0.0, -1.0, 0.0],
-                position,
-                tex_coord,
-            });
-        }
-        // Bottom
-        for i in 0..4 {
-            let position = [
-                0.5 - (i % 4 > 1) as i32 as f32,
-                0.5,
-                0.5 - ((i + 1) % 4 > 1) as i32 as f32,
-            ];
-            let tex_coord = [(5.0 + position[0] + 0.5) / 6.0, position[2] + 0.5];
-
-            vertices.push(Vertex {
-                normal: [0.0, 1.0, 0.0],
-                position,
-                tex_coord,
-            });
-        }
-        // println!("{}", vertices.len());
-
-        let mut indices: Vec<u32> = Vec::new();
-        for i in 0..6 {
-            indices.append(&mut vec![i * 4, i * 4 + 1, (i * 4) + 2]);
-            indices.append(&mut vec![i * 4, i * 4 + 2, i * 4 + 3]);
-        }
-        // println!("{:?}", indices);
-
-        let mesh = Mesh {
-            vertices,
-            indices,
-            material_idx: 0,
-        };
         let mut mat = Material::default();
         if let Some(path) = texture_path {
-            let texture = load_texture(path);
-            mat.base_color_texture = Some(texture);
+            let texture = load_texture_or_placeholder(path);
+            if !texture.is_opaque() {
+                mat.alpha_mode = AlphaMode::Mask;
+            }
+            mat.base_color_texture =
+                Some(SampledTexture::new(Arc::new(texture)).with_sampler(Sampler::pixelated()));
         }
 
-        let model = Model {
-            meshes: vec![mesh],
-            mats: vec![mat],
-        };
-
+        let model = Model::new(vec![Mesh::cube()], vec![mat]);
         Object {
             model,
             position,
@@ -149,106 +84,61 @@ impl Object {
             .unwrap()
             .update_physics(delta);
     }
+    #[allow(clippy::too_many_arguments)]
     pub fn raycast(
         &self,
         ray_origin: [f32; 3],
         ray_dir: [f32; 3],
         dist: f32,
-        fb: &mut Framebuffer,
-        depth_buffer: &mut Framebuffer,
-        mvp: &Matrix,
+        fb: &mut ColorBuffer,
+        depth_buffer: &mut DepthBuffer,
+        mvp: &Mat4,
         draw: bool,
     ) -> (f32, [f32; 3]) {
         let mut mind = dist;
-        let mut d0: Vertex = Vertex {
-            position: [0.0; 3],
-            normal: [0.0; 3],
-            tex_coord: [0.0; 2],
-        };
-        let mut d1: Vertex = Vertex {
-            position: [0.0; 3],
-            normal: [0.0; 3],
-            tex_coord: [0.0; 2],
-        };
-        let mut d2: Vertex = Vertex {
-            position: [0.0; 3],
-            normal: [0.0; 3],
-            tex_coord: [0.0; 2],
-        };
-        let mut minint = Matrix::identity();
+        let mut d0 = Vertex::default();
+        let mut d1 = Vertex::default();
+        let mut d2 = Vertex::default();
+        let mut minint = Vec3::default();
+        let pos_matrix = Mat4::trans(self.position).rotate(self.rotation) * Mat4::scale(self.scale);
+        let origin = Vec3(ray_origin);
+        let ray = Vec3(ray_dir);
         for mesh in self.model.meshes.iter() {
             for i in 0..(mesh.indices.len() / 3) {
-                let scale: Matrix = vec![
-                    vec![self.scale[0], 0.0, 0.0, 0.0],
-                    vec![0.0, self.scale[1], 0.0, 0.0],
-                    vec![0.0, 0.0, self.scale[2], 0.0],
-                    vec![0.0, 0.0, 0.0, 1.0],
-                ]
-                .into();
-                let pos_matrix = (Matrix::trans(self.position).rotate(self.rotation)) * scale;
-                let mut v0 = mesh.vertices[mesh.indices[i * 3] as usize].position;
-                v0 = &pos_matrix * v0;
-                let v0: Matrix = vec![v0.to_vec()].into();
-
-                let mut v1 = mesh.vertices[mesh.indices[i * 3 + 1] as usize].position;
-                v1 = &pos_matrix * v1;
-                let v1: Matrix = vec![v1.to_vec()].into();
-
-                let mut v2 = mesh.vertices[mesh.indices[i * 3 + 2] as usize].position;
-                v2 = &pos_matrix * v2;
-                let v2: Matrix = vec![v2.to_vec()].into();
+                let v0 = &pos_matrix * Vec3(mesh.vertices[mesh.indices[i * 3] as usize].position);
+                let v1 =
+                    &pos_matrix * Vec3(mesh.vertices[mesh.indices[i * 3 + 1] as usize].position);
+                let v2 =
+                    &pos_matrix * Vec3(mesh.vertices[mesh.indices[i * 3 + 2] as usize].position);
 
-                let origin: Matrix = vec![ray_origin.to_vec()].into();
-                let ray: Matrix = vec![ray_dir.to_vec()].into();
-                let e1 = &v1 - &v0;
-                let e2 = &v2 - &v0;
-                let mat: Matrix = vec![e1[0].clone(), e2[0].clone(), ray[0].clone()].into();
-                let det = mat.det();
+                let e1 = v1 - v0;
+                let e2 = v2 - v0;
+                let det = Mat3::from_rows(e1, e2, ray).det();
                 let inv_det = 1.0 / det;
-                let s = &origin - &v0;
-                let mat_u: Matrix = vec![s[0].clone(), e2[0].clone(), ray[0].clone()].into();
-                let u = mat_u.det() * inv_det;
+                let s = origin - v0;
+                let u = Mat3::from_rows(s, e2, ray).det() * inv_det;
                 if !(0.0..=1.0).contains(&u) {
                     continue;
                 }
-                let mat_v: Matrix = vec![e1[0].clone(), s[0].clone(), ray[0].clone()].into();
-                let v = mat_v.det() * inv_det;
+                let v = Mat3::from_rows(e1, s, ray).det() * inv_det;
                 if !(0.0..=1.0).contains(&v) || (u + v > 1.0) {
                     continue;
                 }
-                let mat_t: Matrix = vec![e1[0].clone(), e2[0].clone(), s[0].clone()].into();
-                let t = mat_t.det() * inv_det;
+                let t = Mat3::from_rows(e1, e2, s).det() * inv_det;
                 if t > f32::EPSILON {
-                    let intersection_point = &origin + &(ray.clone() * (-t));
-                    // println!("{:?}", intersection_point[0]);
-                    let dmat = &intersection_point - &origin;
-                    let d = (dmat[0][0] * dmat[0][0]
-                        + dmat[0][1] * dmat[0][1]
-                        + dmat[0][2] * dmat[0][2])
-                        .sqrt();
+                    let intersection_point = origin + ray * (-t);
+                    let d = (intersection_point - origin).length();
                     if d < mind {
                         mind = d;
                         d0 = mesh.vertices[mesh.indices[i * 3] as usize];
                         d1 = mesh.vertices[mesh.indices[i * 3 + 1] as usize];
                         d2 = mesh.vertices[mesh.indices[i * 3 + 2] as usize];
-                        minint = vec![vec![
-                            intersection_point[0][0] * -1.0,
-                            intersection_point[0][1] * -1.0,
-                            intersection_point[0][2] * -1.0,
-                        ]]
-                        .into();
+                        minint = -intersection_point;
                         if draw {
-                            let mut origin = origin * -1.0;
-                            origin[0][2] += 0.01;
-                            let inter = intersection_point * -1.0;
-                            draw_line(
-                                fb,
-                                depth_buffer,
-                                &origin.into(),
-                                &inter.into(),
-                                mvp,
-                                &[255, 0, 0],
-                            );
+                            let mut origin = -origin;
+                            origin[2] += 0.01;
+                            let inter = -intersection_point;
+                            draw_line(fb, depth_buffer, &origin, &inter, mvp, &[255, 0, 0]);
                         }
                     }
                 }
@@ -259,30 +149,9 @@ impl Object {
                 base_col: [1.0, 0.0, 0.0, 1.0],
                 ..Default::default()
             };
-            // d0.position[0] *= -1.0;
-            // d0.position[1] *= -1.0;
-            // d0.position[2] *= -1.0;
-            // d1.position[0] *= -1.0;
-            // d1.position[1] *= -1.0;
-            // d1.position[2] *= -1.0;
-            // d2.position[0] *= -1.0;
-            // d2.position[1] *= -1.0;
-            // d2.position[2] *= -1.0;
-            // draw_line(fb, depth_buffer, &d0.position, &d1.position, mvp);
-            // draw_line(fb, depth_buffer, &d2.position, &d1.position, mvp);
-            // draw_line(fb, depth_buffer, &d0.position, &d2.position, mvp);
-            d0.position = ((&minint) + &Matrix::from(vec![vec![-0.05, -0.05, 0.1]]))[0]
-                .clone()
-                .try_into()
-                .unwrap();
-            d1.position = ((&minint) + &Matrix::from(vec![vec![0.05, -0.05, 0.1]]))[0]
-                .clone()
-                .try_into()
-                .unwrap();
-            d2.position = ((&minint) + &Matrix::from(vec![vec![0.0, 0.1, 0.1]]))[0]
-                .clone()
-                .try_into()
-                .unwrap();
+            d0.position = (minint + Vec3([-0.05, -0.05, 0.1])).0;
+            d1.position = (minint + Vec3([0.05, -0.05, 0.1])).0;
+            d2.position = (minint + Vec3([0.0, 0.1, 0.1])).0;
 
             draw_triangle(
                 fb,
@@ -290,34 +159,86 @@ impl Object {
                 &d0,
                 &d1,
                 &d2,
-                mvp,
-                &Matrix::identity(),
+                &Uniforms::new(mvp, Mat4::identity(), &Lighting::unlit()),
                 &mat,
             );
         }
-        (mind, (minint * -1.0).into())
+        (mind, (-minint).0)
+    }
+    // Renders to ColorBuffer using its properties, given view-projection matrix and lights
+    pub fn render(
+        &self,
+        fb: &mut ColorBuffer,
+        depth_buffer: &mut DepthBuffer,
+        view_proj: &Mat4,
+        lighting: &Lighting,
+    ) {
+        let uniforms = Uniforms::new(view_proj, self.model_matrix(), lighting);
+        if !Frustum::new(&uniforms.mvp).intersects(&self.model.bounds) {
+            CULLED_OBJECTS.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        draw_model(fb, depth_buffer, &self.model, &uniforms);
+    }
+    // Draws the blended parts of the object, after everything opaque was rendered
+    pub fn render_transparent(
+        &self,
+        fb: &mut ColorBuffer,
+        depth_buffer: &mut DepthBuffer,
+        view_proj: &Mat4,
+        lighting: &Lighting,
+    ) {
+        let uniforms = Uniforms::new(view_proj, self.model_matrix(), lighting);
+        if Frustum::new(&uniforms.mvp).intersects(&self.model.bounds) {
+            draw_model_transparent(fb, depth_buffer, &self.model, &uniforms);
+        }
+    }
+    // Distance of the object's center from the camera along the view direction
+    fn view_depth(&self, view_proj: &Mat4) -> f32 {
+        let center = &self.model_matrix() * Vec3(self.model.bounds.center);
+        (view_proj * Vec4::point(center.0)).0[3]
+    }
+    // Draws depth of the object into the shadow map
+    pub fn render_shadow(&self, shadow: &mut ShadowMap) {
+        let model_matrix = self.model_matrix();
+        if Frustum::new(&(shadow.view_proj * model_matrix)).intersects(&self.model.bounds) {
+            shadow.render(&self.model, model_matrix);
+        }
     }
-    // Renders to Framebuffer using its properties and given view-projection matrix
-    pub fn render(&self, fb: &mut Framebuffer, depth_buffer: &mut Framebuffer, view_proj: &Matrix) {
+    pub fn model_matrix(&self) -> Mat4 {
         // Transform by position
-        let pos = [
-            self.position[0] * -1.0,
-            self.position[1] * -1.0,
-            self.position[2] * -1.0,
-        ];
-        let pos_matrix = Matrix::trans(pos).rotate(self.rotation);
+        let pos = [-self.position[0], -self.position[1], -self.position[2]];
+        let pos_matrix = Mat4::trans(pos).rotate(self.rotation);
         // Scale
-        let scale_matrix: Matrix = vec![
-            vec![self.scale[0], 0.0, 0.0, 0.0],
-            vec![0.0, self.scale[1], 0.0, 0.0],
-            vec![0.0, 0.0, self.scale[2], 0.0],
-            vec![0.0, 0.0, 0.0, 1.0],
-        ]
-        .into();
-        let mod_matrix = pos_matrix * scale_matrix;
-        let invmod = mod_matrix.inverse().transpose();
-        let mvp = view_proj * &mod_matrix;
-        draw_model(fb, depth_buffer, &self.model, &mvp, &invmod);
+        pos_matrix * Mat4::scale(self.scale)
+    }
+}
+// Renders objects in two passes: opaque geometry first, then blended one with objects sorted
+// from the farthest
+pub fn render_objects<'a>(
+    objects: impl IntoIterator<Item = &'a Object>,
+    fb: &mut ColorBuffer,
+    depth_buffer: &mut DepthBuffer,
+    view_proj: &Mat4,
+    lighting: &Lighting,
+) {
+    let objects: Vec<&Object> = objects.into_iter().collect();
+    for object in &objects {
+        object.render(fb, depth_buffer, view_proj, lighting);
+    }
+    let mut transparent: Vec<(f32, &Object)> = objects
+        .into_iter()
+        .filter(|object| {
+            object
+                .model
+                .mats
+                .iter()
+                .any(|mat| mat.alpha_mode == AlphaMode::Blend)
+        })
+        .map(|object| (object.view_depth(view_proj), object))
+        .collect();
+    transparent.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (_, object) in transparent {
+        object.render_transparent(fb, depth_buffer, view_proj, lighting);
     }
 }
-//
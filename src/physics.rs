@@ -1,4 +1,3 @@
-use crate::types::Matrix;
 use std::sync::{Arc, Mutex};
 
 const G: f32 = 6.6743015e-11;
@@ -62,7 +61,7 @@ impl Physics {
         self.mass_center
     }
     pub fn apply_gravity(&mut self) {
-        let mut registry = REGISTRY.lock().unwrap();
+        let registry = REGISTRY.lock().unwrap();
         for phys in registry.iter() {
             let phys = phys.try_lock();
             if phys.is_err() {
@@ -73,7 +72,7 @@ impl Physics {
             let disx = phys.mass_center[0] - self.mass_center[0];
             let disy = phys.mass_center[1] - self.mass_center[1];
             let disz = phys.mass_center[2] - self.mass_center[2];
-            let mut dis = ((disx * disx) + (disy * disy) + (disz * disz)).sqrt();
+            let dis = ((disx * disx) + (disy * disy) + (disz * disz)).sqrt();
             if dis < 2.0 {
                 continue;
             }
@@ -85,6 +84,6 @@ impl Physics {
             self.force[2] += (disz / dis) * forc;
             println!("f {:?}", self.force);
         }
-        println!("");
+        println!();
     }
 }
@@ -0,0 +1,184 @@
+use crate::{
+    depth::DepthBuffer,
+    draw::{draw_model_depth, CullMode, Uniforms},
+    light::Lighting,
+    model::Model,
+    types::{Mat4, Vec3, Vec4},
+};
+
+// Depth range in front of the center, in multiples of the radius
+const CASTER_REACH: f32 = 2.0;
+
+// Depth of the scene seen from a directional light, covering a cube around a center point
+#[derive(Clone, Debug)]
+pub struct ShadowMap {
+    pub depth: DepthBuffer,
+    pub view_proj: Mat4,
+    // Index of the directional light in Lighting::lights casting the shadows
+    pub light: usize,
+    // Half the edge of the covered cube in world units
+    pub radius: f32,
+    // Offset against shadow acne, constant part in world units and slope part in texels, which
+    // grows as the surface turns away from the light
+    pub bias: f32,
+    pub slope_bias: f32,
+    // Filter radius in texels, 1 averages 3x3 samples
+    pub pcf: i32,
+    pub enabled: bool,
+}
+impl ShadowMap {
+    // Resolution is at least one texel
+    pub fn new(resolution: usize) -> Self {
+        let resolution = resolution.max(1);
+        ShadowMap {
+            depth: DepthBuffer::new(resolution, resolution),
+            view_proj: Mat4::identity(),
+            light: 0,
+            radius: 32.0,
+            bias: 0.01,
+            slope_bias: 1.5,
+            pcf: 1,
+            enabled: true,
+        }
+    }
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+    pub fn with_pcf(mut self, pcf: i32) -> Self {
+        self.pcf = pcf;
+        self
+    }
+    pub fn resolution(&self) -> usize {
+        self.depth.width()
+    }
+    pub fn set_resolution(&mut self, resolution: usize) {
+        let resolution = resolution.max(1);
+        self.depth.resize(resolution, resolution);
+    }
+    // Clears the map and points it along dir, centered on center
+    pub fn begin(&mut self, dir: [f32; 3], center: [f32; 3]) {
+        let view = Mat4::look_dir(center, dir);
+        // Reaches further towards the light, so casters outside the cube still throw shadows
+        let proj = Mat4::orthographic(
+            self.radius,
+            self.radius,
+            -self.radius * CASTER_REACH,
+            self.radius,
+        );
+        self.view_proj = proj * view;
+        self.depth.clear();
+    }
+    // Adds depth of the model placed by model_matrix
+    pub fn render(&mut self, model: &Model, model_matrix: Mat4) {
+        let lighting = Lighting::unlit();
+        // Both sides cast shadows, so open meshes don't leak light
+        let uniforms =
+            Uniforms::new(&self.view_proj, model_matrix, &lighting).with_cull(CullMode::None);
+        draw_model_depth(&mut self.depth, model, &uniforms);
+    }
+    // Fraction of the light reaching pos, n_dot_l is the cosine between normal and light
+    pub fn visibility(&self, pos: &Vec3, n_dot_l: f32) -> f32 {
+        let p = &self.view_proj * Vec4::point(pos.0);
+        if p[0].abs() > 1.0 || p[1].abs() > 1.0 || !(0.0..=1.0).contains(&p[2]) {
+            return 1.0;
+        }
+        let size = self.resolution() as f32;
+        // Same mapping as the rasterizer uses for pixel centers
+        let x = ((p[0] * 0.5 + 0.5) * size).round() as i32;
+        let y = ((p[1] * 0.5 + 0.5) * size).round() as i32;
+        let texel = 2.0 * self.radius / size;
+        let tan = ((1.0 - n_dot_l * n_dot_l).sqrt() / n_dot_l).min(10.0);
+        let bias = self.bias + self.slope_bias * (self.pcf as f32 + 1.0) * texel * tan;
+        let z = p[2] - bias / (self.radius * (CASTER_REACH + 1.0));
+
+        let max = self.resolution() as i32 - 1;
+        let mut lit = 0;
+        for dy in -self.pcf..=self.pcf {
+            for dx in -self.pcf..=self.pcf {
+                let sx = (x + dx).clamp(0, max) as usize;
+                let sy = (y + dy).clamp(0, max) as usize;
+                if z <= self.depth.get(sx, sy) {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / ((2 * self.pcf + 1) * (2 * self.pcf + 1)) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR: [f32; 3] = [0.0, -1.0, 0.0];
+
+    // 8x8 map of a 2x2x2 cube around the origin, lit straight from above
+    fn map(pcf: i32) -> ShadowMap {
+        let mut map = ShadowMap::new(8).with_radius(1.0).with_pcf(pcf);
+        map.begin(DIR, [0.0; 3]);
+        map
+    }
+    // Depth and texel column of the point in the map
+    fn project(map: &ShadowMap, pos: [f32; 3]) -> (f32, usize) {
+        let p = &map.view_proj * Vec4::point(pos);
+        let x = ((p[0] * 0.5 + 0.5) * map.resolution() as f32).round() as usize;
+        (p[2], x)
+    }
+    // Occluder at the height of the origin covering the columns before the given one
+    fn occlude_left_of(map: &mut ShadowMap, column: usize) {
+        let (z, _) = project(map, [0.0; 3]);
+        for y in 0..map.resolution() {
+            for x in 0..column {
+                map.depth.set(x, y, z);
+            }
+        }
+    }
+
+    #[test]
+    fn points_behind_occluder_are_shadowed() {
+        let mut map = map(1);
+        let below = Vec3([0.1, -0.5, 0.1]);
+        let above = Vec3([0.1, 0.5, 0.1]);
+        assert_eq!(map.visibility(&below, 1.0), 1.0);
+        occlude_left_of(&mut map, 8);
+        assert_eq!(map.visibility(&below, 1.0), 0.0);
+        assert_eq!(map.visibility(&above, 1.0), 1.0);
+        // Outside of the covered cube everything is lit
+        assert_eq!(map.visibility(&Vec3([5.0, -0.5, 0.0]), 1.0), 1.0);
+    }
+
+    #[test]
+    fn pcf_averages_neighbouring_texels() {
+        for (pcf, expected) in [(0, 1.0), (1, 6.0 / 9.0), (2, 15.0 / 25.0)] {
+            let mut map = map(pcf);
+            let pos = [0.0, -0.5, 0.0];
+            let (_, column) = project(&map, pos);
+            occlude_left_of(&mut map, column);
+            let visibility = map.visibility(&Vec3(pos), 1.0);
+            assert!((visibility - expected).abs() < 1e-6, "{pcf}: {visibility}");
+        }
+    }
+
+    #[test]
+    fn bias_grows_with_slope() {
+        let mut map = map(1);
+        occlude_left_of(&mut map, 8);
+        // Just under the occluder, hidden by the constant bias only head on
+        let pos = Vec3([0.0, -0.005, 0.0]);
+        assert_eq!(map.visibility(&pos, 1.0), 1.0);
+        let pos = Vec3([0.0, -0.1, 0.0]);
+        assert_eq!(map.visibility(&pos, 1.0), 0.0);
+        assert_eq!(map.visibility(&pos, 0.5), 1.0);
+    }
+
+    #[test]
+    fn zero_resolution_is_one_texel() {
+        let mut map = ShadowMap::new(0);
+        assert_eq!(map.resolution(), 1);
+        map.set_resolution(0);
+        assert_eq!(map.resolution(), 1);
+        map.begin(DIR, [0.0; 3]);
+        assert_eq!(map.visibility(&Vec3([0.0; 3]), 1.0), 1.0);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    noise::{hash2, Perlin},
+    voxel::{BlockId, VoxelWorld, AIR},
+};
+
+// Blocks the terrain is built of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TerrainBlocks {
+    pub grass: BlockId,
+    pub dirt: BlockId,
+    pub stone: BlockId,
+    pub log: BlockId,
+    pub leaves: BlockId,
+}
+
+// Rolling hills of grass over dirt over stone, with caves and trees. The same seed always
+// generates the same world, no matter which columns are generated first.
+#[derive(Clone, Debug)]
+pub struct Terrain {
+    seed: u64,
+    pub blocks: TerrainBlocks,
+    // Average height of the surface and how far hills reach above and below it
+    pub base_height: i32,
+    pub amplitude: f32,
+    // Blocks per period of the largest hills
+    pub scale: f32,
+    pub dirt_depth: i32,
+    // Lowest generated layer
+    pub bottom: i32,
+    // Share of the underground hollowed out into caves, 0 turns them off
+    pub caves: f32,
+    // Chance of a tree growing on a grass column
+    pub trees: f32,
+    heights: Perlin,
+    tunnels: Perlin,
+}
+impl Terrain {
+    pub fn new(seed: u64, blocks: TerrainBlocks) -> Self {
+        Terrain {
+            seed,
+            blocks,
+            base_height: 0,
+            amplitude: 10.0,
+            scale: 48.0,
+            dirt_depth: 3,
+            bottom: -24,
+            caves: 0.15,
+            trees: 0.01,
+            heights: Perlin::new(seed),
+            tunnels: Perlin::new(seed.wrapping_add(1)),
+        }
+    }
+    pub fn with_height(mut self, base_height: i32, amplitude: f32) -> Self {
+        self.base_height = base_height;
+        self.amplitude = amplitude;
+        self
+    }
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+    pub fn with_bottom(mut self, bottom: i32) -> Self {
+        self.bottom = bottom;
+        self
+    }
+    pub fn with_caves(mut self, caves: f32) -> Self {
+        self.caves = caves;
+        self
+    }
+    pub fn with_trees(mut self, trees: f32) -> Self {
+        self.trees = trees;
+        self
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    // Y of the grass block of the column
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let n = self
+            .heights
+            .fbm2(x as f32 / self.scale, z as f32 / self.scale, 4);
+        self.base_height + (n * 2.0 * self.amplitude).round() as i32
+    }
+    // Caves are where the 3D noise is close to zero, which forms winding tunnels. They stay
+    // under the dirt, so the surface is never holed.
+    fn is_cave(&self, pos: [i32; 3], height: i32) -> bool {
+        if pos[1] > height - self.dirt_depth || pos[1] <= self.bottom {
+            return false;
+        }
+        let [x, y, z] = pos.map(|c| c as f32 / 16.0);
+        // Squashed vertically, so tunnels run more sideways than up
+        let n = self.tunnels.fbm3(x, y * 1.5, z, 2);
+        n.abs() < self.caves * 0.5
+    }
+    // Fills the columns min..max on x and z
+    pub fn generate(&self, world: &mut VoxelWorld, min: [i32; 2], max: [i32; 2]) {
+        let b = self.blocks;
+        for x in min[0]..max[0] {
+            for z in min[1]..max[1] {
+                let height = self.height(x, z);
+                for y in self.bottom..=height {
+                    if self.is_cave([x, y, z], height) {
+                        continue;
+                    }
+                    let block = if y == height {
+                        b.grass
+                    } else if y > height - self.dirt_depth {
+                        b.dirt
+                    } else {
+                        b.stone
+                    };
+                    world.set([x, y, z], block);
+                }
+            }
+        }
+        for x in min[0]..max[0] {
+            for z in min[1]..max[1] {
+                if self.has_tree(x, z) {
+                    self.grow_tree(world, [x, self.height(x, z) + 1, z]);
+                }
+            }
+        }
+    }
+    // Trees grow on grass, at most one in every 5x5 area so their crowns don't merge
+    fn has_tree(&self, x: i32, z: i32) -> bool {
+        let chance = |x, z| hash2(self.seed, x, z);
+        let own = chance(x, z);
+        own < self.trees
+            && (-2..=2).all(|i| (-2..=2).all(|j| (i, j) == (0, 0) || chance(x + i, z + j) > own))
+    }
+    // Trunk with a crown of leaves around its top, leaves don't replace other blocks
+    fn grow_tree(&self, world: &mut VoxelWorld, base: [i32; 3]) {
+        let [x, y, z] = base;
+        let trunk = 4 + (hash2(self.seed.wrapping_add(2), x, z) * 3.0) as i32;
+        let top = y + trunk;
+        for dy in -2..=1 {
+            let radius: i32 = if dy < 0 { 2 } else { 1 };
+            for i in -radius..=radius {
+                for j in -radius..=radius {
+                    // Rounded corners
+                    if radius == 2 && i.abs() == 2 && j.abs() == 2 {
+                        continue;
+                    }
+                    let pos = [x + i, top + dy, z + j];
+                    if world.get(pos) == AIR {
+                        world.set(pos, self.blocks.leaves);
+                    }
+                }
+            }
+        }
+        for trunk_y in y..top {
+            world.set([x, trunk_y, z], self.blocks.log);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::tests::world_of;
+
+    fn world() -> (VoxelWorld, TerrainBlocks) {
+        let (world, ids) = world_of(&["grass", "dirt", "stone", "log", "leaves"]);
+        let blocks = TerrainBlocks {
+            grass: ids[0],
+            dirt: ids[1],
+            stone: ids[2],
+            log: ids[3],
+            leaves: ids[4],
+        };
+        (world, blocks)
+    }
+
+    fn column(world: &VoxelWorld, x: i32, z: i32) -> Vec<BlockId> {
+        (-30..30).map(|y| world.get([x, y, z])).collect()
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        let (mut a, blocks) = world();
+        let (mut b, _) = world();
+        let (mut c, _) = world();
+        Terrain::new(5, blocks).generate(&mut a, [-16, -16], [16, 16]);
+        // Generated in two parts
+        let terrain = Terrain::new(5, blocks);
+        terrain.generate(&mut b, [-16, -16], [0, 16]);
+        terrain.generate(&mut b, [0, -16], [16, 16]);
+        Terrain::new(6, blocks).generate(&mut c, [-16, -16], [16, 16]);
+        let mut differs = false;
+        for x in -16..16 {
+            for z in -16..16 {
+                assert_eq!(column(&a, x, z), column(&b, x, z));
+                differs |= column(&a, x, z) != column(&c, x, z);
+            }
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn columns_are_layered() {
+        let (mut world, blocks) = world();
+        let terrain = Terrain::new(1, blocks).with_caves(0.0).with_trees(0.0);
+        terrain.generate(&mut world, [0, 0], [8, 8]);
+        for x in 0..8 {
+            for z in 0..8 {
+                let h = terrain.height(x, z);
+                assert_eq!(world.get([x, h, z]), blocks.grass);
+                assert_eq!(world.get([x, h + 1, z]), AIR);
+                assert_eq!(world.get([x, h - 1, z]), blocks.dirt);
+                assert_eq!(world.get([x, h - 3, z]), blocks.stone);
+                assert_eq!(world.get([x, terrain.bottom, z]), blocks.stone);
+            }
+        }
+    }
+}
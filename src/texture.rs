@@ -1,91 +1,684 @@
-use image::{GenericImageView, ImageReader};
+use crate::atlas::UvRect;
+use crate::color::{decode_srgb8, encode_srgb8, srgb_to_linear};
+use crate::error::{Error, Result};
+use gltf::image::Format;
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageReader};
 use std::path::Path;
+use std::sync::Arc;
 
+// Texture coordinate with its screen space derivatives, which select the mip level
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TexCoord {
+    pub uv: [f32; 2],
+    pub ddx: [f32; 2],
+    pub ddy: [f32; 2],
+    // Part of the texture uv repeats inside, uv and its derivatives count repeats of it
+    pub tile: Option<UvRect>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Linear,
+}
+// What happens to coordinates outside of 0..1
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat,
+}
+impl Wrap {
+    fn apply(self, i: i64, size: u32) -> usize {
+        let n = size as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::ClampToEdge => i.clamp(0, n - 1),
+            Wrap::MirroredRepeat => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as usize
+    }
+}
+// Filtering and wrapping of a texture, defaults to trilinear with repeat
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sampler {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    // Blending between mip levels, None samples only the full size image
+    pub mipmap_filter: Option<Filter>,
+    pub wrap_s: Wrap,
+    pub wrap_t: Wrap,
+}
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_filter: Some(Filter::Linear),
+            wrap_s: Wrap::Repeat,
+            wrap_t: Wrap::Repeat,
+        }
+    }
+}
+impl Sampler {
+    // Keeps texels sharp up close, for pixel art
+    pub fn pixelated() -> Self {
+        Sampler {
+            mag_filter: Filter::Nearest,
+            ..Default::default()
+        }
+    }
+    // Missing filters fall back to trilinear
+    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
+        use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+        let wrap = |mode| match mode {
+            WrappingMode::Repeat => Wrap::Repeat,
+            WrappingMode::ClampToEdge => Wrap::ClampToEdge,
+            WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+        };
+        let (min_filter, mipmap_filter) = match sampler.min_filter() {
+            Some(MinFilter::Nearest) => (Filter::Nearest, None),
+            Some(MinFilter::Linear) => (Filter::Linear, None),
+            Some(MinFilter::NearestMipmapNearest) => (Filter::Nearest, Some(Filter::Nearest)),
+            Some(MinFilter::LinearMipmapNearest) => (Filter::Linear, Some(Filter::Nearest)),
+            Some(MinFilter::NearestMipmapLinear) => (Filter::Nearest, Some(Filter::Linear)),
+            Some(MinFilter::LinearMipmapLinear) | None => (Filter::Linear, Some(Filter::Linear)),
+        };
+        Sampler {
+            mag_filter: match sampler.mag_filter() {
+                Some(MagFilter::Nearest) => Filter::Nearest,
+                Some(MagFilter::Linear) | None => Filter::Linear,
+            },
+            min_filter,
+            mipmap_filter,
+            wrap_s: wrap(sampler.wrap_s()),
+            wrap_t: wrap(sampler.wrap_t()),
+        }
+    }
+}
+
+// Texture shared between materials together with the sampler of one material slot
+#[derive(Clone, Debug)]
+pub struct SampledTexture {
+    pub texture: Arc<Texture>,
+    pub sampler: Sampler,
+}
+impl SampledTexture {
+    pub fn new(texture: Arc<Texture>) -> Self {
+        SampledTexture {
+            texture,
+            sampler: Sampler::default(),
+        }
+    }
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = sampler;
+        self
+    }
+    pub fn sample(&self, tex: &TexCoord) -> [f32; 4] {
+        self.texture.sample(&self.sampler, tex)
+    }
+}
+
+// RGBA texels, 8-bit images stay compact and deeper ones keep their precision as floats
 #[derive(Debug, Clone)]
-pub struct Texture {
-    pub data: Vec<u8>,
+pub enum Texels {
+    Rgba8(Vec<[u8; 4]>),
+    // sRGB encoded color with linear alpha, decoded to linear on fetch
+    Srgba8(Vec<[u8; 4]>),
+    Rgba32F(Vec<[f32; 4]>),
+}
+
+// One level of the mip chain
+#[derive(Debug, Clone)]
+pub struct MipLevel {
+    pub texels: Texels,
     pub width: u32,
     pub height: u32,
-    pub channels: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Texture {
+    // Full image first, every next level halves the size down to 1x1
+    pub levels: Vec<MipLevel>,
 }
 impl Texture {
-    pub fn sample_pixel(&self, x: f32, y: f32) -> [f32; 4] {
-        let invx = 1.0 / self.width as f32;
-        let invy = 1.0 / self.height as f32;
-
-        let tl = self.get_pixel(x - invx, y - invy);
-        let tr = self.get_pixel(x + invx, y - invy);
-        let bl = self.get_pixel(x - invx, y + invy);
-        let br = self.get_pixel(x + invx, y + invy);
-
-        let x = x * self.width as f32;
-        let y = y * self.height as f32;
-        let dx = x - ((x as i32) as f32);
-        let dy = y - ((y as i32) as f32);
-
-        let bot = [
-            bl[0] + ((br[0] - bl[0]) * dx),
-            bl[1] + ((br[1] - bl[1]) * dx),
-            bl[2] + ((br[2] - bl[2]) * dx),
-            bl[3] + ((br[3] - bl[3]) * dx),
-        ];
-        let top = [
-            tl[0] + ((tr[0] - tl[0]) * dx),
-            tl[1] + ((tr[1] - tl[1]) * dx),
-            tl[2] + ((tr[2] - tl[2]) * dx),
-            tl[3] + ((tr[3] - tl[3]) * dx),
-        ];
-        [
-            bot[0] + ((top[0] - bot[0]) * dy),
-            bot[1] + ((top[1] - bot[1]) * dy),
-            bot[2] + ((top[2] - bot[2]) * dy),
-            bot[3] + ((top[3] - bot[3]) * dy),
-        ]
+    // Gray and gray-alpha images are expanded to RGBA, 16-bit and float ones are stored as floats.
+    // Values are taken as they are, use from_srgb_image for color textures.
+    pub fn from_image(img: DynamicImage) -> Texture {
+        Texture::build(img, false)
     }
-    pub fn get_pixel(&self, x: f32, y: f32) -> [f32; 4] {
-        let mut x = x;
-        let mut y = y;
-        x *= self.width as f32;
-        y *= self.height as f32;
-        let x = x as u32;
-        let y = y as u32;
-        let x = x % (self.width);
-        let y = y % (self.height);
-        let x = x as usize;
-        let y = y as usize;
-        match self.channels {
-            4 => {
-                let data: &Vec<(u8, u8, u8, u8)> = unsafe { std::mem::transmute(&self.data) };
-                let pixel = &data[x + y * self.width as usize];
-                [
-                    pixel.0 as f32 / 255.99,
-                    pixel.1 as f32 / 255.99,
-                    pixel.2 as f32 / 255.99,
-                    pixel.3 as f32 / 255.99,
-                ]
+    // Color texture whose 8-bit texels are sRGB encoded, sampling returns linear values
+    pub fn from_srgb_image(img: DynamicImage) -> Texture {
+        Texture::build(img, true)
+    }
+    fn build(img: DynamicImage, srgb: bool) -> Texture {
+        let (width, height) = img.dimensions();
+        let texels = match img {
+            DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_) => {
+                let texels = img.to_rgba8().pixels().map(|p| p.0).collect();
+                if srgb {
+                    Texels::Srgba8(texels)
+                } else {
+                    Texels::Rgba8(texels)
+                }
+            }
+            // Float images are linear already, 16-bit ones are encoded like 8-bit ones
+            img => {
+                let float = matches!(
+                    img,
+                    DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+                );
+                let mut texels: Vec<[f32; 4]> = img.to_rgba32f().pixels().map(|p| p.0).collect();
+                if srgb && !float {
+                    for texel in &mut texels {
+                        for c in &mut texel[..3] {
+                            *c = srgb_to_linear(*c);
+                        }
+                    }
+                }
+                Texels::Rgba32F(texels)
             }
-            3 => {
-                let data: &Vec<(u8, u8, u8)> = unsafe { std::mem::transmute(&self.data) };
-                let pixel = &data[x + y * self.width as usize];
+        };
+        Texture::from_level(MipLevel {
+            texels,
+            width,
+            height,
+        })
+    }
+    pub(crate) fn from_level(level: MipLevel) -> Texture {
+        let mut levels = vec![level];
+        while let Some(next) = levels.last().unwrap().downsample() {
+            levels.push(next);
+        }
+        Texture { levels }
+    }
+    // Copy of a rectangle of the full image with its own mip chain
+    pub fn sub_texture(&self, x: u32, y: u32, width: u32, height: u32) -> Texture {
+        fn crop<T: Copy>(texels: &[T], stride: u32, x: u32, y: u32, w: u32, h: u32) -> Vec<T> {
+            (y..y + h)
+                .flat_map(|row| {
+                    let start = (row * stride + x) as usize;
+                    texels[start..start + w as usize].iter().copied()
+                })
+                .collect()
+        }
+        let full = &self.levels[0];
+        assert!(x + width <= full.width && y + height <= full.height);
+        let texels = match &full.texels {
+            Texels::Rgba8(texels) => Texels::Rgba8(crop(texels, full.width, x, y, width, height)),
+            Texels::Srgba8(texels) => Texels::Srgba8(crop(texels, full.width, x, y, width, height)),
+            Texels::Rgba32F(texels) => {
+                Texels::Rgba32F(crop(texels, full.width, x, y, width, height))
+            }
+        };
+        Texture::from_level(MipLevel {
+            texels,
+            width,
+            height,
+        })
+    }
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+    // Whether every texel of the full image has alpha of 1
+    pub fn is_opaque(&self) -> bool {
+        match &self.levels[0].texels {
+            Texels::Rgba8(texels) | Texels::Srgba8(texels) => texels.iter().all(|t| t[3] == 255),
+            Texels::Rgba32F(texels) => texels.iter().all(|t| t[3] >= 1.0),
+        }
+    }
+    // Magenta and black checkerboard standing in for textures that failed to load. It is six
+    // tiles wide, so every face of a cube gets the same 8x8 pattern.
+    pub fn placeholder() -> Texture {
+        let img = image::RgbImage::from_fn(48, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgb([255, 0, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        Texture::from_srgb_image(DynamicImage::ImageRgb8(img))
+    }
+    // Converts image decoded by gltf::import, base color and emissive images are sRGB. Path of
+    // the glTF file is only used in errors.
+    pub fn from_gltf(data: &gltf::image::Data, srgb: bool, path: &str) -> Result<Texture> {
+        let (w, h) = (data.width, data.height);
+        let px = &data.pixels;
+        let u16s = || -> Vec<u16> {
+            px.chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect()
+        };
+        let f32s = || -> Vec<f32> {
+            px.chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()
+        };
+        let img = match data.format {
+            Format::R8 => ImageBuffer::from_raw(w, h, px.to_vec()).map(DynamicImage::ImageLuma8),
+            Format::R8G8 => ImageBuffer::from_raw(w, h, px.to_vec()).map(DynamicImage::ImageLumaA8),
+            Format::R8G8B8 => ImageBuffer::from_raw(w, h, px.to_vec()).map(DynamicImage::ImageRgb8),
+            Format::R8G8B8A8 => {
+                ImageBuffer::from_raw(w, h, px.to_vec()).map(DynamicImage::ImageRgba8)
+            }
+            Format::R16 => ImageBuffer::from_raw(w, h, u16s()).map(DynamicImage::ImageLuma16),
+            Format::R16G16 => ImageBuffer::from_raw(w, h, u16s()).map(DynamicImage::ImageLumaA16),
+            Format::R16G16B16 => ImageBuffer::from_raw(w, h, u16s()).map(DynamicImage::ImageRgb16),
+            Format::R16G16B16A16 => {
+                ImageBuffer::from_raw(w, h, u16s()).map(DynamicImage::ImageRgba16)
+            }
+            Format::R32G32B32FLOAT => {
+                ImageBuffer::from_raw(w, h, f32s()).map(DynamicImage::ImageRgb32F)
+            }
+            Format::R32G32B32A32FLOAT => {
+                ImageBuffer::from_raw(w, h, f32s()).map(DynamicImage::ImageRgba32F)
+            }
+        };
+        let img = img.ok_or_else(|| Error::Decode {
+            path: path.to_string(),
+            message: "image data doesn't match its size".to_string(),
+        })?;
+        Ok(Texture::build(img, srgb))
+    }
+    // Filtered sample, the mip level comes from the derivatives of the coordinate
+    pub fn sample(&self, sampler: &Sampler, tex: &TexCoord) -> [f32; 4] {
+        if let Some(tile) = tex.tile {
+            let size = tile.size();
+            let tex = TexCoord {
+                uv: tile.repeat(tex.uv),
+                ddx: std::array::from_fn(|i| tex.ddx[i] * size[i]),
+                ddy: std::array::from_fn(|i| tex.ddy[i] * size[i]),
+                tile: None,
+            };
+            return self.sample(sampler, &tex);
+        }
+        let (w, h) = (self.width() as f32, self.height() as f32);
+        let len = |d: [f32; 2]| ((d[0] * w).powi(2) + (d[1] * h).powi(2)).sqrt();
+        let lod = len(tex.ddx).max(len(tex.ddy)).log2();
+        if lod.is_nan() || lod <= 0.0 {
+            return self.sample_level(0, sampler.mag_filter, sampler, tex.uv);
+        }
+        let max_level = (self.levels.len() - 1) as f32;
+        let lod = lod.min(max_level);
+        match sampler.mipmap_filter {
+            None => self.sample_level(0, sampler.min_filter, sampler, tex.uv),
+            Some(Filter::Nearest) => {
+                self.sample_level(lod.round() as usize, sampler.min_filter, sampler, tex.uv)
+            }
+            Some(Filter::Linear) => {
+                let level = lod.floor() as usize;
+                let t = lod - level as f32;
+                let a = self.sample_level(level, sampler.min_filter, sampler, tex.uv);
+                if t == 0.0 {
+                    return a;
+                }
+                let b = self.sample_level(level + 1, sampler.min_filter, sampler, tex.uv);
+                std::array::from_fn(|c| a[c] + (b[c] - a[c]) * t)
+            }
+        }
+    }
+    fn sample_level(
+        &self,
+        level: usize,
+        filter: Filter,
+        sampler: &Sampler,
+        uv: [f32; 2],
+    ) -> [f32; 4] {
+        let mip = &self.levels[level];
+        let x = uv[0] * mip.width as f32;
+        let y = uv[1] * mip.height as f32;
+        let fetch = |x: i64, y: i64| {
+            let x = sampler.wrap_s.apply(x, mip.width);
+            let y = sampler.wrap_t.apply(y, mip.height);
+            mip.texel(x, y)
+        };
+        match filter {
+            Filter::Nearest => fetch(x.floor() as i64, y.floor() as i64),
+            Filter::Linear => {
+                // Texel centers sit at half coordinates
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let tl = fetch(x0, y0);
+                let tr = fetch(x0 + 1, y0);
+                let bl = fetch(x0, y0 + 1);
+                let br = fetch(x0 + 1, y0 + 1);
+                std::array::from_fn(|c| {
+                    let top = tl[c] + (tr[c] - tl[c]) * fx;
+                    let bot = bl[c] + (br[c] - bl[c]) * fx;
+                    top + (bot - top) * fy
+                })
+            }
+        }
+    }
+    // Nearest texel of the full image with repeat
+    pub fn get_pixel(&self, x: f32, y: f32) -> [f32; 4] {
+        let sampler = Sampler::default();
+        self.sample_level(0, Filter::Nearest, &sampler, [x, y])
+    }
+}
+impl MipLevel {
+    pub fn texel(&self, x: usize, y: usize) -> [f32; 4] {
+        let i = x + y * self.width as usize;
+        match &self.texels {
+            Texels::Rgba8(texels) => texels[i].map(|c| c as f32 / 255.0),
+            Texels::Srgba8(texels) => {
+                let [r, g, b, a] = texels[i];
                 [
-                    pixel.0 as f32 / 255.99,
-                    pixel.1 as f32 / 255.99,
-                    pixel.2 as f32 / 255.99,
-                    1.0,
+                    decode_srgb8(r),
+                    decode_srgb8(g),
+                    decode_srgb8(b),
+                    a as f32 / 255.0,
                 ]
             }
-            _ => panic!("Invalid texture channel count"),
+            Texels::Rgba32F(texels) => texels[i],
+        }
+    }
+    // Next mip level by averaging 2x2 blocks, odd edges repeat their last texel
+    fn downsample(&self) -> Option<MipLevel> {
+        if self.width == 1 && self.height == 1 {
+            return None;
         }
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        // Indices of the 2x2 block of every texel of the next level
+        let blocks = (0..height as usize).flat_map(|y| {
+            (0..width as usize).map(move |x| {
+                let xs = [2 * x, 2 * x + 1].map(|x| x.min(self.width as usize - 1));
+                let ys = [2 * y, 2 * y + 1].map(|y| y.min(self.height as usize - 1));
+                [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(i, j)| xs[i] + ys[j] * self.width as usize)
+            })
+        });
+        let texels = match &self.texels {
+            Texels::Rgba8(texels) => Texels::Rgba8(
+                blocks
+                    .map(|block| {
+                        std::array::from_fn(|c| {
+                            let sum: u32 = block.iter().map(|&i| texels[i][c] as u32).sum();
+                            ((sum + 2) / 4) as u8
+                        })
+                    })
+                    .collect(),
+            ),
+            // Averaged in linear space, so mips don't darken
+            Texels::Srgba8(texels) => Texels::Srgba8(
+                blocks
+                    .map(|block| {
+                        std::array::from_fn(|c| {
+                            if c == 3 {
+                                let sum: u32 = block.iter().map(|&i| texels[i][c] as u32).sum();
+                                return ((sum + 2) / 4) as u8;
+                            }
+                            let sum: f32 = block.iter().map(|&i| decode_srgb8(texels[i][c])).sum();
+                            encode_srgb8(sum / 4.0)
+                        })
+                    })
+                    .collect(),
+            ),
+            Texels::Rgba32F(texels) => Texels::Rgba32F(
+                blocks
+                    .map(|block| {
+                        std::array::from_fn(|c| {
+                            block.iter().map(|&i| texels[i][c]).sum::<f32>() / 4.0
+                        })
+                    })
+                    .collect(),
+            ),
+        };
+        Some(MipLevel {
+            texels,
+            width,
+            height,
+        })
     }
 }
 
 pub fn load_texture(path: &str) -> Texture {
-    let path = Path::new(path);
-    let img = ImageReader::open(path).unwrap().decode().unwrap();
-    Texture {
-        width: img.dimensions().0,
-        height: img.dimensions().1,
-        channels: img.color().bytes_per_pixel() as usize,
-        data: img.into_bytes(),
+    try_load_texture(path).unwrap_or_else(|err| panic!("{err}"))
+}
+// Loads texture, on failure reports the error and returns the checkerboard placeholder
+pub fn load_texture_or_placeholder(path: &str) -> Texture {
+    try_load_texture(path).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        Texture::placeholder()
+    })
+}
+pub fn try_load_texture(path: &str) -> Result<Texture> {
+    let img = ImageReader::open(Path::new(path))
+        .map_err(|source| Error::Io {
+            path: path.to_string(),
+            source,
+        })?
+        .decode()
+        .map_err(|err| Error::from_image(path, err))?;
+    // Textures loaded from files are block and object colors
+    Ok(Texture::from_srgb_image(img))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayAlphaImage, GrayImage, ImageBuffer, LumaA, Rgb, Rgba};
+
+    fn texel(img: DynamicImage) -> [f32; 4] {
+        Texture::from_image(img).levels[0].texel(1, 0)
+    }
+
+    #[test]
+    fn gltf_image_of_wrong_size_is_decode_error() {
+        let data = gltf::image::Data {
+            pixels: vec![255; 4],
+            format: Format::R8G8B8A8,
+            width: 2,
+            height: 2,
+        };
+        let err = Texture::from_gltf(&data, true, "model.gltf").unwrap_err();
+        assert!(matches!(err, Error::Decode { .. }), "{err}");
+    }
+
+    #[test]
+    fn formats_are_expanded_to_rgba() {
+        let gray = GrayImage::from_fn(2, 2, |x, _| image::Luma([x as u8 * 255]));
+        assert_eq!(texel(DynamicImage::ImageLuma8(gray)), [1.0, 1.0, 1.0, 1.0]);
+
+        let gray_alpha = GrayAlphaImage::from_pixel(2, 2, LumaA([0, 255]));
+        assert_eq!(
+            texel(DynamicImage::ImageLumaA8(gray_alpha)),
+            [0.0, 0.0, 0.0, 1.0]
+        );
+
+        let rgb16: ImageBuffer<Rgb<u16>, Vec<u16>> =
+            ImageBuffer::from_pixel(2, 2, Rgb([65535, 0, 32768]));
+        let t = Texture::from_image(DynamicImage::ImageRgb16(rgb16));
+        assert!(matches!(t.levels[0].texels, Texels::Rgba32F(_)));
+        let [r, g, b, a] = t.levels[0].texel(0, 1);
+        assert_eq!([r, g, a], [1.0, 0.0, 1.0]);
+        assert!((b - 0.5).abs() < 1e-4);
+
+        // Float images keep values outside of 0..1
+        let hdr: ImageBuffer<Rgba<f32>, Vec<f32>> =
+            ImageBuffer::from_pixel(2, 2, Rgba([4.0, 0.25, 0.0, 1.0]));
+        assert_eq!(
+            texel(DynamicImage::ImageRgba32F(hdr)),
+            [4.0, 0.25, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn deep_srgb_images_are_decoded_but_float_ones_are_not() {
+        let rgba16: ImageBuffer<Rgba<u16>, Vec<u16>> =
+            ImageBuffer::from_pixel(2, 2, Rgba([65535, 0, 32768, 32768]));
+        let t = Texture::from_srgb_image(DynamicImage::ImageRgba16(rgba16));
+        let [r, g, b, a] = t.levels[0].texel(0, 0);
+        assert_eq!([r, g], [1.0, 0.0]);
+        assert!((b - srgb_to_linear(0.5)).abs() < 1e-4);
+        // Alpha is never encoded
+        assert!((a - 0.5).abs() < 1e-4);
+        let gray16: ImageBuffer<image::Luma<u16>, Vec<u16>> =
+            ImageBuffer::from_pixel(2, 2, image::Luma([32768]));
+        let t = Texture::from_srgb_image(DynamicImage::ImageLuma16(gray16));
+        assert!((t.levels[0].texel(0, 0)[0] - srgb_to_linear(0.5)).abs() < 1e-4);
+
+        let hdr: ImageBuffer<Rgb<f32>, Vec<f32>> = ImageBuffer::from_pixel(2, 2, Rgb([0.5; 3]));
+        let t = Texture::from_srgb_image(DynamicImage::ImageRgb32F(hdr));
+        assert_eq!(t.levels[0].texel(0, 0), [0.5, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn mip_chain_averages_down_to_one_texel() {
+        let img = GrayImage::from_fn(5, 3, |x, y| {
+            image::Luma([if (x + y) % 2 == 0 { 255 } else { 0 }])
+        });
+        let t = Texture::from_image(DynamicImage::ImageLuma8(img));
+        let sizes: Vec<(u32, u32)> = t.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+        let [r, ..] = t.levels[1].texel(0, 0);
+        assert!((r - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn srgb_texels_decode_and_average_linearly() {
+        let img = GrayImage::from_fn(2, 2, |x, y| {
+            image::Luma([if (x + y) % 2 == 0 { 255 } else { 0 }])
+        });
+        let t = Texture::from_srgb_image(DynamicImage::ImageLuma8(img));
+        assert_eq!(t.levels[0].texel(0, 0), [1.0; 4]);
+        // Half of the light, not half of the encoded value
+        let [r, .., a] = t.levels[1].texel(0, 0);
+        assert!((r - 0.5).abs() < 0.01);
+        assert_eq!(a, 1.0);
+        let Texels::Srgba8(texels) = &t.levels[1].texels else {
+            panic!("sRGB mips should stay 8-bit");
+        };
+        assert_eq!(texels[0][0], 188);
+    }
+
+    #[test]
+    fn wrap_modes_map_outside_coordinates() {
+        let wrapped = |wrap: Wrap| [-5, -1, 0, 3, 4, 5, 9].map(|i| wrap.apply(i, 4));
+        assert_eq!(wrapped(Wrap::Repeat), [3, 3, 0, 3, 0, 1, 1]);
+        assert_eq!(wrapped(Wrap::ClampToEdge), [0, 0, 0, 3, 3, 3, 3]);
+        assert_eq!(wrapped(Wrap::MirroredRepeat), [3, 0, 0, 3, 3, 2, 1]);
+    }
+
+    #[test]
+    fn gltf_samplers_map_filters_and_wrapping() {
+        let json = r#"{
+  "asset": {"version": "2.0"},
+  "samplers": [
+    {"magFilter": 9728, "minFilter": 9984, "wrapS": 33071, "wrapT": 33648},
+    {"magFilter": 9729, "minFilter": 9729, "wrapS": 10497},
+    {"minFilter": 9986},
+    {}
+  ]
+}"#;
+        let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let samplers: Vec<Sampler> = gltf.samplers().map(|s| Sampler::from_gltf(&s)).collect();
+        assert_eq!(
+            samplers[0],
+            Sampler {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                mipmap_filter: Some(Filter::Nearest),
+                wrap_s: Wrap::ClampToEdge,
+                wrap_t: Wrap::MirroredRepeat,
+            }
+        );
+        // Plain linear minification doesn't use the mips
+        assert_eq!(samplers[1].min_filter, Filter::Linear);
+        assert_eq!(samplers[1].mipmap_filter, None);
+        assert_eq!(samplers[1].wrap_t, Wrap::Repeat);
+        assert_eq!(samplers[2].min_filter, Filter::Nearest);
+        assert_eq!(samplers[2].mipmap_filter, Some(Filter::Linear));
+        assert_eq!(samplers[3], Sampler::default());
+    }
+
+    #[test]
+    fn minification_selects_mip_level() {
+        // 8x8 texture whose every level holds its own index
+        let mut t = Texture::from_image(DynamicImage::ImageLuma8(GrayImage::new(8, 8)));
+        assert_eq!(t.levels.len(), 4);
+        for (i, level) in t.levels.iter_mut().enumerate() {
+            let len = (level.width * level.height) as usize;
+            level.texels = Texels::Rgba32F(vec![[i as f32; 4]; len]);
+        }
+        // Texels of the full image per screen pixel, along x and y
+        let level = |sampler: &Sampler, ddx: f32, ddy: f32| {
+            let tex = TexCoord {
+                uv: [0.3, 0.6],
+                ddx: [ddx / 8.0, 0.0],
+                ddy: [0.0, ddy / 8.0],
+                ..Default::default()
+            };
+            t.sample(sampler, &tex)[0]
+        };
+        let trilinear = Sampler::default();
+        assert_eq!(level(&trilinear, 0.5, 0.5), 0.0);
+        assert_eq!(level(&trilinear, 1.0, 1.0), 0.0);
+        assert_eq!(level(&trilinear, 2.0, 1.0), 1.0);
+        assert_eq!(level(&trilinear, 1.0, 4.0), 2.0);
+        assert!((level(&trilinear, 3.0, 1.0) - 3f32.log2()).abs() < 1e-5);
+        // Past the smallest level it stays there
+        assert_eq!(level(&trilinear, 64.0, 64.0), 3.0);
+        let nearest = Sampler {
+            mipmap_filter: Some(Filter::Nearest),
+            ..Default::default()
+        };
+        assert_eq!(level(&nearest, 3.0, 1.0), 2.0);
+        assert_eq!(level(&nearest, 2.5, 1.0), 1.0);
+        let no_mips = Sampler {
+            mipmap_filter: None,
+            ..Default::default()
+        };
+        assert_eq!(level(&no_mips, 4.0, 4.0), 0.0);
+    }
+
+    #[test]
+    fn coordinates_repeat_inside_tile() {
+        // 4x1 texture whose texels hold their x, the tile is the right half
+        let texels = (0..4).map(|x| [x as f32; 4]).collect();
+        let mut t = Texture::from_level(MipLevel {
+            texels: Texels::Rgba32F(texels),
+            width: 4,
+            height: 1,
+        });
+        for level in &mut t.levels[1..] {
+            level.texels = Texels::Rgba32F(vec![[-1.0; 4]; level.width as usize]);
+        }
+        let tile = UvRect {
+            min: [0.5, 0.0],
+            max: [1.0, 1.0],
+        };
+        let sample = |u: f32, ddx: f32| {
+            let tex = TexCoord {
+                uv: [u, 0.5],
+                ddx: [ddx, 0.0],
+                ddy: [0.0, 0.0],
+                tile: Some(tile),
+            };
+            t.sample(&Sampler::pixelated(), &tex)[0]
+        };
+        assert_eq!(sample(0.25, 0.0), 2.0);
+        assert_eq!(sample(0.75, 0.0), 3.0);
+        assert_eq!(sample(2.25, 0.0), 2.0);
+        assert_eq!(sample(-0.25, 0.0), 3.0);
+        // One repeat per pixel is 2 texels of the tile, not 4 of the texture
+        assert_eq!(sample(0.25, 0.5), 2.0);
+        assert_eq!(sample(0.25, 1.0), -1.0);
     }
 }
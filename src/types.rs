@@ -1,152 +1,321 @@
-use std::ops::{Add, Deref, DerefMut, Mul, Sub};
+use std::ops::{Add, Deref, DerefMut, Mul, Neg, Sub};
+#[cfg(feature = "matrix-stats")]
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 // Global counter for matrix multiplications
+#[cfg(feature = "matrix-stats")]
 static MATRIX_MUL_COUNT: AtomicUsize = AtomicUsize::new(0);
-#[derive(Clone, Debug)]
-pub struct Matrix(Vec<Vec<f32>>);
 
-impl Deref for Matrix {
-    type Target = Vec<Vec<f32>>;
+#[inline(always)]
+fn count_mul() {
+    #[cfg(feature = "matrix-stats")]
+    MATRIX_MUL_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec3(pub [f32; 3]);
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec4(pub [f32; 4]);
+// Matrices are stored row by row and multiply row vectors (v * M), so translation lives in
+// the last row. `a * b` applies `b` first and `a` second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3(pub [[f32; 3]; 3]);
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4(pub [[f32; 4]; 4]);
+
+impl Deref for Vec3 {
+    type Target = [f32; 3];
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
-
-impl DerefMut for Matrix {
+impl DerefMut for Vec3 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
+impl From<[f32; 3]> for Vec3 {
+    fn from(v: [f32; 3]) -> Self {
+        Vec3(v)
+    }
+}
+impl From<Vec3> for [f32; 3] {
+    fn from(v: Vec3) -> Self {
+        v.0
+    }
+}
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, rhs: Self) -> Self::Output {
+        Vec3([self[0] + rhs[0], self[1] + rhs[1], self[2] + rhs[2]])
+    }
+}
+impl Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Vec3([self[0] - rhs[0], self[1] - rhs[1], self[2] - rhs[2]])
+    }
+}
+impl Mul<f32> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, scalar: f32) -> Self::Output {
+        Vec3([self[0] * scalar, self[1] * scalar, self[2] * scalar])
+    }
+}
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Self::Output {
+        Vec3([-self[0], -self[1], -self[2]])
+    }
+}
+impl Vec3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Vec3([x, y, z])
+    }
+    pub fn dot(&self, rhs: &Vec3) -> f32 {
+        self[0] * rhs[0] + self[1] * rhs[1] + self[2] * rhs[2]
+    }
+    pub fn cross(&self, rhs: &Vec3) -> Vec3 {
+        Vec3([
+            self[1] * rhs[2] - self[2] * rhs[1],
+            self[2] * rhs[0] - self[0] * rhs[2],
+            self[0] * rhs[1] - self[1] * rhs[0],
+        ])
+    }
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+    // Returns unit vector, zero vector stays zero
+    pub fn normalize(&self) -> Vec3 {
+        let len = self.length();
+        if len == 0.0 {
+            return *self;
+        }
+        *self * (1.0 / len)
+    }
+}
+
+impl Deref for Vec4 {
+    type Target = [f32; 4];
 
-impl From<Vec<Vec<f32>>> for Matrix {
-    fn from(vec: Vec<Vec<f32>>) -> Self {
-        Matrix(vec)
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl DerefMut for Vec4 {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+impl From<[f32; 4]> for Vec4 {
+    fn from(v: [f32; 4]) -> Self {
+        Vec4(v)
     }
 }
-impl Into<[f32; 3]> for Matrix {
-    fn into(self) -> [f32; 3] {
-        [self[0][0], self[0][1], self[0][2]]
+impl From<Vec4> for [f32; 4] {
+    fn from(v: Vec4) -> Self {
+        v.0
+    }
+}
+impl Add for Vec4 {
+    type Output = Vec4;
+    fn add(self, rhs: Self) -> Self::Output {
+        Vec4([
+            self[0] + rhs[0],
+            self[1] + rhs[1],
+            self[2] + rhs[2],
+            self[3] + rhs[3],
+        ])
+    }
+}
+impl Sub for Vec4 {
+    type Output = Vec4;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Vec4([
+            self[0] - rhs[0],
+            self[1] - rhs[1],
+            self[2] - rhs[2],
+            self[3] - rhs[3],
+        ])
+    }
+}
+impl Mul<f32> for Vec4 {
+    type Output = Vec4;
+    fn mul(self, scalar: f32) -> Self::Output {
+        Vec4([
+            self[0] * scalar,
+            self[1] * scalar,
+            self[2] * scalar,
+            self[3] * scalar,
+        ])
+    }
+}
+impl Vec4 {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Vec4([x, y, z, w])
+    }
+    // Position with w = 1, affected by translation
+    pub fn point(p: [f32; 3]) -> Self {
+        Vec4([p[0], p[1], p[2], 1.0])
+    }
+    // Direction with w = 0, not affected by translation
+    pub fn dir(d: [f32; 3]) -> Self {
+        Vec4([d[0], d[1], d[2], 0.0])
+    }
+    pub fn dot(&self, rhs: &Vec4) -> f32 {
+        self[0] * rhs[0] + self[1] * rhs[1] + self[2] * rhs[2] + self[3] * rhs[3]
+    }
+    pub fn xyz(&self) -> Vec3 {
+        Vec3([self[0], self[1], self[2]])
     }
 }
 
-impl Mul for Matrix {
-    type Output = Matrix;
-    fn mul(self, rhs: Self) -> Self::Output {
-        MATRIX_MUL_COUNT.fetch_add(1, Ordering::Relaxed);
-        let mut res: Matrix = vec![vec![0.0; self[0].len()]; rhs.len()].into();
-        for i in 0..rhs.len() {
-            for j in 0..self[0].len() {
-                for k in 0..rhs[0].len() {
-                    res[i][j] += rhs[i][k] * self[k][j];
-                }
-            }
-        }
+impl Deref for Mat3 {
+    type Target = [[f32; 3]; 3];
 
-        res
+    fn deref(&self) -> &Self::Target {
+        &self.0
     }
 }
-impl Mul for &Matrix {
-    type Output = Matrix;
-    fn mul(self, rhs: Self) -> Self::Output {
-        MATRIX_MUL_COUNT.fetch_add(1, Ordering::Relaxed);
-        let mut res: Matrix = vec![vec![0.0; self[0].len()]; rhs.len()].into();
-        for i in 0..rhs.len() {
-            for j in 0..self[0].len() {
-                for k in 0..rhs[0].len() {
-                    res[i][j] += rhs[i][k] * self[k][j];
-                }
-            }
-        }
+impl DerefMut for Mat3 {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+impl Mul<Vec3> for &Mat3 {
+    type Output = Vec3;
 
+    fn mul(self, v: Vec3) -> Vec3 {
+        count_mul();
+        let mut res = Vec3::default();
+        for j in 0..3 {
+            res[j] = v[0] * self[0][j] + v[1] * self[1][j] + v[2] * self[2][j];
+        }
         res
     }
 }
-impl Mul<f32> for Matrix {
-    type Output = Matrix;
-
-    fn mul(self, scalar: f32) -> Self::Output {
-        let mut res: Matrix = self.clone();
-        for i in 0..self.len() {
-            for j in 0..self[0].len() {
-                res[i][j] *= scalar;
+impl Mat3 {
+    pub fn identity() -> Mat3 {
+        Mat3([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+    pub fn from_rows(r0: Vec3, r1: Vec3, r2: Vec3) -> Mat3 {
+        Mat3([r0.0, r1.0, r2.0])
+    }
+    pub fn det(&self) -> f32 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+    pub fn transpose(&self) -> Self {
+        let mut transposed = *self;
+        for i in 0..3 {
+            for j in 0..3 {
+                transposed[i][j] = self[j][i];
             }
         }
-        res
+        transposed
     }
 }
-impl Mul<[f32; 3]> for &Matrix {
-    type Output = [f32; 3];
 
-    fn mul(self, v0: [f32; 3]) -> [f32; 3] {
-        let mut v: Matrix = vec![v0.to_vec()].into();
+impl Deref for Mat4 {
+    type Target = [[f32; 4]; 4];
 
-        v[0].push(1.0);
-        v = self * &v;
-        [v[0][0], v[0][1], v[0][2]]
+    fn deref(&self) -> &Self::Target {
+        &self.0
     }
 }
-impl Add for Matrix {
-    type Output = Matrix;
-    fn add(self, rhs: Self) -> Self::Output {
-        let mut res: Matrix = self.clone();
-        for i in 0..self.len() {
-            for j in 0..self[0].len() {
-                res[i][j] += rhs[i][j];
+impl DerefMut for Mat4 {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+impl Mul for &Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Self) -> Self::Output {
+        *self * *rhs
+    }
+}
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Self) -> Self::Output {
+        count_mul();
+        let mut res = Mat4([[0.0; 4]; 4]);
+        for i in 0..4 {
+            for j in 0..4 {
+                for k in 0..4 {
+                    res[i][j] += rhs[i][k] * self[k][j];
+                }
             }
         }
         res
     }
 }
-impl Add for &Matrix {
-    type Output = Matrix;
-    fn add(self, rhs: Self) -> Self::Output {
-        let mut res: Matrix = self.clone();
-        for i in 0..self.len() {
-            for j in 0..self[0].len() {
-                res[i][j] += rhs[i][j];
+impl Mul<f32> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, scalar: f32) -> Self::Output {
+        let mut res = self;
+        for row in res.iter_mut() {
+            for a in row.iter_mut() {
+                *a *= scalar;
             }
         }
         res
     }
 }
-impl Add<f32> for Matrix {
-    type Output = Matrix;
-    fn add(self, scalar: f32) -> Self::Output {
-        let mut res: Matrix = self.clone();
-        for i in 0..self.len() {
-            for j in 0..self[0].len() {
-                res[i][j] += scalar;
-            }
+impl Mul<Vec4> for &Mat4 {
+    type Output = Vec4;
+
+    fn mul(self, v: Vec4) -> Vec4 {
+        count_mul();
+        let mut res = Vec4::default();
+        for j in 0..4 {
+            res[j] = v[0] * self[0][j] + v[1] * self[1][j] + v[2] * self[2][j] + v[3] * self[3][j];
         }
         res
     }
 }
-impl Sub for &Matrix {
-    type Output = Matrix;
-    fn sub(self, rhs: Self) -> Self::Output {
-        let mut res: Matrix = self.clone();
-        for i in 0..self.len() {
-            for j in 0..self[0].len() {
-                res[i][j] -= rhs[i][j];
+// Transforms point (w = 1) without perspective divide
+impl Mul<Vec3> for &Mat4 {
+    type Output = Vec3;
+
+    fn mul(self, v: Vec3) -> Vec3 {
+        (self * Vec4::point(v.0)).xyz()
+    }
+}
+impl Add for Mat4 {
+    type Output = Mat4;
+    fn add(self, rhs: Self) -> Self::Output {
+        let mut res = self;
+        for i in 0..4 {
+            for j in 0..4 {
+                res[i][j] += rhs[i][j];
             }
         }
         res
     }
 }
-impl Matrix {
+impl Mat4 {
     // Returns view matrix defined by z_offset
-    pub fn trans(offset: [f32; 3]) -> Matrix {
-        vec![
-            vec![1.0, 0.0, 0.0, 0.0],
-            vec![0.0, 1.0, 0.0, 0.0],
-            vec![0.0, 0.0, 1.0, 0.0],
-            vec![offset[0], offset[1], offset[2], 1.0],
-        ]
-        .into()
-    }
-    pub fn rotate(self, rotation: [f32; 3]) -> Matrix {
+    pub fn trans(offset: [f32; 3]) -> Mat4 {
+        Mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [offset[0], offset[1], offset[2], 1.0],
+        ])
+    }
+    pub fn scale(scale: [f32; 3]) -> Mat4 {
+        Mat4([
+            [scale[0], 0.0, 0.0, 0.0],
+            [0.0, scale[1], 0.0, 0.0],
+            [0.0, 0.0, scale[2], 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+    pub fn rotate(self, rotation: [f32; 3]) -> Mat4 {
         // Apply rotation
         let sina = rotation[0].sin();
         let cosa = rotation[0].cos();
@@ -155,161 +324,240 @@ impl Matrix {
         let sinc = rotation[2].sin();
         let cosc = rotation[2].cos();
 
-        let rot_matrix: Matrix = vec![
-            vec![
+        let rot_matrix = Mat4([
+            [
                 cosb * cosc,
                 (sina * sinb * cosc) - (cosa * sinc),
                 (cosa * sinb * cosc) + (sina * sinc),
                 0.0,
             ],
-            vec![
+            [
                 cosb * sinc,
                 (sina * sinb * sinc) + (cosa * cosc),
                 (cosa * sinb * sinc) - (sina * cosc),
                 0.0,
             ],
-            vec![-sinb, sina * cosb, cosa * cosb, 0.0],
-            vec![0.0, 0.0, 0.0, 1.0],
-        ]
-        .into();
+            [-sinb, sina * cosb, cosa * cosb, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
         self * rot_matrix
     }
     // Creates projection matrix by given params
-    pub fn projection(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Matrix {
+    pub fn projection(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Mat4 {
         let f = 1.0 / (fov_y / 2.0).tan();
         let nf = 1.0 / (near - far);
-        vec![
-            vec![f / aspect_ratio, 0.0, 0.0, 0.0],
-            vec![0.0, f, 0.0, 0.0],
-            vec![0.0, 0.0, (far + near) * nf, -1.0],
-            vec![0.0, 0.0, (2.0 * far * near) * nf, 0.0],
-        ]
-        .into()
+        Mat4([
+            [f / aspect_ratio, 0.0, 0.0, 0.0],
+            [0.0, f, 0.0, 0.0],
+            [0.0, 0.0, (far + near) * nf, -1.0],
+            [0.0, 0.0, (2.0 * far * near) * nf, 0.0],
+        ])
+    }
+    // Same as projection, but maps near plane to depth 1 and far plane to 0 for better precision
+    pub fn projection_reverse_z(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Mat4 {
+        let f = 1.0 / (fov_y / 2.0).tan();
+        let fn_ = 1.0 / (far - near);
+        Mat4([
+            [f / aspect_ratio, 0.0, 0.0, 0.0],
+            [0.0, f, 0.0, 0.0],
+            [0.0, 0.0, near * fn_, -1.0],
+            [0.0, 0.0, far * near * fn_, 0.0],
+        ])
+    }
+    // Orthographic projection of the box |x| <= half_width, |y| <= half_height looking down -z,
+    // with depth 0 at near and 1 at far
+    pub fn orthographic(half_width: f32, half_height: f32, near: f32, far: f32) -> Mat4 {
+        let fn_ = 1.0 / (far - near);
+        Mat4([
+            [1.0 / half_width, 0.0, 0.0, 0.0],
+            [0.0, 1.0 / half_height, 0.0, 0.0],
+            [0.0, 0.0, -fn_, 0.0],
+            [0.0, 0.0, -near * fn_, 1.0],
+        ])
+    }
+    // View matrix of an eye at pos looking along dir. The axes keep the handedness of the camera
+    // view, so triangles facing the eye keep their winding.
+    pub fn look_dir(pos: [f32; 3], dir: [f32; 3]) -> Mat4 {
+        let f = Vec3(dir).normalize();
+        let helper = if f[1].abs() < 0.99 {
+            Vec3([0.0, 1.0, 0.0])
+        } else {
+            Vec3([1.0, 0.0, 0.0])
+        };
+        let r = helper.cross(&f).normalize();
+        let u = r.cross(&f);
+        let eye = Vec3(pos);
+        Mat4([
+            [r[0], u[0], -f[0], 0.0],
+            [r[1], u[1], -f[1], 0.0],
+            [r[2], u[2], -f[2], 0.0],
+            [-eye.dot(&r), -eye.dot(&u), eye.dot(&f), 1.0],
+        ])
     }
     // Identity
-    pub fn identity() -> Matrix {
-        vec![
-            vec![1.0, 0.0, 0.0, 0.0],
-            vec![0.0, 1.0, 0.0, 0.0],
-            vec![0.0, 0.0, 1.0, 0.0],
-            vec![0.0, 0.0, 0.0, 1.0],
-        ]
-        .into()
-    }
-    pub fn gaussian_elim(&self) -> (Self, u32) {
-        let mut swaps = 0;
-        let mut res = self.clone();
-        let n = res.len();
-        for i in 0..n {
+    pub fn identity() -> Mat4 {
+        Mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+    pub fn det(&self) -> f32 {
+        let mut res = self.0;
+        let mut det = 1.0;
+        for i in 0..4 {
             let mut max_row = i;
-            for j in i + 1..n {
+            for j in i + 1..4 {
                 if res[j][i].abs() > res[max_row][i].abs() {
                     max_row = j;
                 }
             }
             if max_row != i {
-                swaps += 1;
+                res.swap(i, max_row);
+                det = -det;
             }
-            let temp = res[i].clone();
-            res[i] = res[max_row].clone();
-            res[max_row] = temp;
-
             if res[i][i] == 0.0 {
-                return (res, swaps);
+                return 0.0;
             }
-            for j in i + 1..n {
-                let a = res[j][i] / res[i][i];
-                // println!("{:?}", a);
-                for k in i..res[j].len() {
-                    res[j][k] -= res[i][k] * a;
+            det *= res[i][i];
+            let pivot = res[i];
+            for row in res.iter_mut().skip(i + 1) {
+                let a = row[i] / pivot[i];
+                for k in i..4 {
+                    row[k] -= pivot[k] * a;
                 }
             }
-            // println!("{:?}", res);
-        }
-        (res, swaps)
-    }
-    pub fn det(&self) -> f32 {
-        let elim = self.gaussian_elim();
-        let mut a = 1.0;
-        for i in 0..self.len() {
-            a *= elim.0[i][i];
         }
-        a / (-2.0 * ((elim.1 % 2) as f32) + 1.0)
+        det
     }
+    // Gauss-Jordan elimination, singular matrices (det of 0) return identity
     pub fn inverse(&self) -> Self {
-        let mut augmat = self.clone();
-
-        // Create an identity matrix of the same size
-        let n = augmat.len();
-        for i in 0..n {
-            augmat[i].extend(vec![0.0; n]); // Extend each row by n elements
-            augmat[i][i + n] = 1.0; // Identity on the right side
-        }
+        let mut m = self.0;
+        let mut inv = Mat4::identity().0;
 
-        // Perform Gauss-Jordan elimination
-        for i in 0..n {
+        for i in 0..4 {
             // Find the row with the maximum element in the i-th column
             let mut max_row = i;
-            for j in i + 1..n {
-                if augmat[j][i].abs() > augmat[max_row][i].abs() {
+            for j in i + 1..4 {
+                if m[j][i].abs() > m[max_row][i].abs() {
                     max_row = j;
                 }
             }
-
-            // Swap rows if necessary
             if max_row != i {
-                augmat.swap(i, max_row);
+                m.swap(i, max_row);
+                inv.swap(i, max_row);
             }
 
             // If the pivot is zero, the matrix is singular (no inverse)
-            if augmat[i][i] == 0.0 {
-                return augmat; // No inverse exists
+            if m[i][i] == 0.0 {
+                return Mat4::identity();
             }
 
             // Normalize the pivot row
-            let pivot = augmat[i][i];
-            for j in 0..2 * n {
-                augmat[i][j] /= pivot;
+            let pivot = 1.0 / m[i][i];
+            for j in 0..4 {
+                m[i][j] *= pivot;
+                inv[i][j] *= pivot;
             }
 
             // Eliminate the i-th column in all other rows
-            for j in 0..n {
+            for j in 0..4 {
                 if j != i {
-                    let factor = augmat[j][i];
-                    for k in 0..2 * n {
-                        augmat[j][k] -= augmat[i][k] * factor;
+                    let factor = m[j][i];
+                    for k in 0..4 {
+                        m[j][k] -= m[i][k] * factor;
+                        inv[j][k] -= inv[i][k] * factor;
                     }
                 }
             }
         }
-
-        // Extract the inverse matrix (right half of the augmented matrix)
-        let mut inverse = Vec::with_capacity(n);
-        for i in 0..n {
-            let mut row = Vec::with_capacity(n);
-            for j in n..2 * n {
-                row.push(augmat[i][j]);
-            }
-            inverse.push(row);
-        }
-        inverse.into()
+        Mat4(inv)
     }
-    pub fn transpose(&mut self) -> Self {
-        let n = self.len();
-        let mut transposed: Matrix = vec![vec![0.0; n]; n].into();
-
-        for i in 0..n {
-            for j in 0..n {
+    pub fn transpose(&self) -> Self {
+        let mut transposed = *self;
+        for i in 0..4 {
+            for j in 0..4 {
                 transposed[i][j] = self[j][i];
             }
         }
-
         transposed
     }
+    // Upper 3x3 of the inverse-transpose, used to bring normals along with the model
+    pub fn normal_matrix(&self) -> Mat3 {
+        let it = self.inverse().transpose();
+        Mat3([
+            [it[0][0], it[0][1], it[0][2]],
+            [it[1][0], it[1][1], it[1][2]],
+            [it[2][0], it[2][1], it[2][2]],
+        ])
+    }
 }
+#[cfg(feature = "matrix-stats")]
 pub fn get_matrix_mul_count() -> usize {
     MATRIX_MUL_COUNT.load(Ordering::Relaxed)
 }
+#[cfg(feature = "matrix-stats")]
 pub fn reset_matrix_mul_count() {
     MATRIX_MUL_COUNT.store(0, Ordering::Relaxed);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) {
+        for (row_a, row_b) in a.iter().zip(b) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < 1e-5, "{a:?} != {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_transform() {
+        let m =
+            Mat4::trans([1.0, -2.0, 3.0]).rotate([0.3, -1.2, 2.0]) * Mat4::scale([2.0, 0.5, 4.0]);
+        assert_close(&(m * m.inverse()).0, &Mat4::identity().0);
+        assert_close(&(m.inverse() * m).0, &Mat4::identity().0);
+    }
+
+    #[test]
+    fn det_matches_hand_computed() {
+        // Scaling multiplies volume by 2 * 3 * 4, rotation and translation keep it
+        let m =
+            Mat4::trans([5.0, 1.0, -1.0]).rotate([0.7, 0.2, -0.4]) * Mat4::scale([2.0, 3.0, 4.0]);
+        assert!((m.det() - 24.0).abs() < 1e-4);
+        // Expanded by cofactors along the first row
+        let m = Mat4([
+            [2.0, 0.0, 1.0, 0.0],
+            [1.0, 3.0, 0.0, 0.0],
+            [0.0, 1.0, 4.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert!((m.det() - 25.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn singular_matrix_inverts_to_identity() {
+        let m = Mat4([
+            [1.0, 2.0, 3.0, 0.0],
+            [2.0, 4.0, 6.0, 0.0],
+            [0.0, 1.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_eq!(m.det(), 0.0);
+        assert_eq!(m.inverse().0, Mat4::identity().0);
+    }
+
+    #[test]
+    fn normal_matrix_undoes_scale() {
+        let n = (Mat4::trans([3.0, 0.0, 1.0]) * Mat4::scale([2.0, 4.0, 8.0])).normal_matrix();
+        let expected = [[0.5, 0.0, 0.0], [0.0, 0.25, 0.0], [0.0, 0.0, 0.125]];
+        for (row, expected) in n.0.iter().zip(expected) {
+            for (x, y) in row.iter().zip(expected) {
+                assert!((x - y).abs() < 1e-6);
+            }
+        }
+    }
+}
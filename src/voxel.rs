@@ -0,0 +1,374 @@
+use crate::{
+    atlas::{Atlas, AtlasBuilder, UvRect},
+    block::BlockRegistry,
+    mesher::{mesh_chunk, FACES},
+    model::{AlphaMode, Material, Model},
+    object::Object,
+    texture::{load_texture_or_placeholder, SampledTexture, Sampler, Texture, Wrap},
+};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+// Edge length of a chunk in blocks
+pub const CHUNK_SIZE: usize = 16;
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+// Type of block, 0 is air and the rest index the BlockRegistry
+pub type BlockId = u16;
+pub const AIR: BlockId = 0;
+
+// 16x16x16 blocks and the mesh built from them
+struct Chunk {
+    blocks: Box<[BlockId; CHUNK_VOLUME]>,
+    // Blocks other than air, empty chunks are dropped
+    filled: usize,
+    mesh: Option<Object>,
+    dirty: bool,
+}
+impl Chunk {
+    fn new() -> Self {
+        Chunk {
+            blocks: Box::new([AIR; CHUNK_VOLUME]),
+            filled: 0,
+            mesh: None,
+            dirty: true,
+        }
+    }
+    fn index(local: [usize; 3]) -> usize {
+        local[0] + (local[1] + local[2] * CHUNK_SIZE) * CHUNK_SIZE
+    }
+}
+
+// Block the ray stopped at and the side it entered through
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockHit {
+    pub pos: [i32; 3],
+    // Points out of the entered face, zero when the ray starts inside the block
+    pub normal: [i32; 3],
+    pub dist: f32,
+    pub point: [f32; 3],
+}
+
+// Grid of unit blocks stored in chunks. Block [x, y, z] is the cube centered at that point, in
+// the space of the player position. Like objects it is rendered mirrored through the origin.
+pub struct VoxelWorld {
+    chunks: HashMap<[i32; 3], Chunk>,
+    registry: BlockRegistry,
+    // Textures of all blocks packed together
+    atlas: Atlas,
+    // Atlas name of every face of every block type, in the order of the mesher's faces
+    faces: Vec<String>,
+    material: Material,
+    // Merge faces of chunk meshes into larger rectangles
+    greedy: bool,
+}
+impl VoxelWorld {
+    // Empty world of the registered block types, drawn with a single material of their packed
+    // textures. Images shared by more faces are loaded once.
+    pub fn new(registry: BlockRegistry) -> Self {
+        let mut images: HashMap<String, Texture> = HashMap::new();
+        for (_, block) in registry.iter() {
+            for face in [&block.top, &block.side, &block.bottom] {
+                images
+                    .entry(face.path.clone())
+                    .or_insert_with(|| load_texture_or_placeholder(&face.path));
+            }
+        }
+        Self::from_images(registry, &images)
+    }
+    // Same as new, but the images of the faces are looked up by path instead of loaded. Missing
+    // ones are replaced by the placeholder.
+    pub fn from_images(registry: BlockRegistry, images: &HashMap<String, Texture>) -> Self {
+        let placeholder = Texture::placeholder();
+        let mut builder = AtlasBuilder::new();
+        let mut faces = Vec::new();
+        for (_, block) in registry.iter() {
+            for face in [
+                &block.side,
+                &block.side,
+                &block.side,
+                &block.side,
+                &block.top,
+                &block.bottom,
+            ] {
+                let name = face.to_string();
+                if !builder.contains(&name) {
+                    let image = images.get(&face.path).unwrap_or(&placeholder);
+                    // Strips are made of square tiles, ones past the end of the strip are
+                    // reported like missing images and show the first tile of the placeholder.
+                    // BlockRegistry::load already rejects them.
+                    let size = image.height();
+                    match face.tile {
+                        Some(tile) if (tile + 1) * size <= image.width() => {
+                            builder.add(&name, &image.sub_texture(tile * size, 0, size, size));
+                        }
+                        Some(_) => {
+                            eprintln!("{}: {name} is past the end of the strip", block.name);
+                            let size = placeholder.height();
+                            builder.add(&name, &placeholder.sub_texture(0, 0, size, size));
+                        }
+                        None => builder.add(&name, image),
+                    }
+                }
+                faces.push(name);
+            }
+        }
+        let atlas = builder.build();
+        // Alpha tested if any block is transparent
+        let mut material = Material::default();
+        if registry.iter().any(|(_, block)| block.transparent) {
+            material.alpha_mode = AlphaMode::Mask;
+        }
+        let sampler = Sampler {
+            wrap_s: Wrap::ClampToEdge,
+            wrap_t: Wrap::ClampToEdge,
+            ..Sampler::pixelated()
+        };
+        material.base_color_texture =
+            Some(SampledTexture::new(atlas.texture.clone()).with_sampler(sampler));
+        VoxelWorld {
+            chunks: HashMap::new(),
+            registry,
+            atlas,
+            faces,
+            material,
+            greedy: false,
+        }
+    }
+    // Merged faces repeat their tile of the atlas, which the mesher gives to the vertices
+    pub fn with_greedy(mut self, greedy: bool) -> Self {
+        self.greedy = greedy;
+        self
+    }
+    pub fn greedy(&self) -> bool {
+        self.greedy
+    }
+    pub fn registry(&self) -> &BlockRegistry {
+        &self.registry
+    }
+    pub fn atlas(&self) -> &Atlas {
+        &self.atlas
+    }
+    // Material every chunk is drawn with
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+    // Part of the atlas one face of the block shows
+    pub fn face_rect(&self, id: BlockId, face: usize) -> UvRect {
+        let name = &self.faces[(id as usize - 1) * FACES + face];
+        self.atlas.rect(name).unwrap()
+    }
+    pub fn is_transparent(&self, id: BlockId) -> bool {
+        self.registry.get(id).is_some_and(|block| block.transparent)
+    }
+    pub fn is_solid(&self, id: BlockId) -> bool {
+        self.registry.get(id).is_some_and(|block| block.solid)
+    }
+    fn split(pos: [i32; 3]) -> ([i32; 3], [usize; 3]) {
+        let size = CHUNK_SIZE as i32;
+        (
+            pos.map(|c| c.div_euclid(size)),
+            pos.map(|c| c.rem_euclid(size) as usize),
+        )
+    }
+    pub fn get(&self, pos: [i32; 3]) -> BlockId {
+        let (key, local) = Self::split(pos);
+        self.chunks
+            .get(&key)
+            .map_or(AIR, |chunk| chunk.blocks[Chunk::index(local)])
+    }
+    // Ids missing from the registry are ignored, there is nothing to draw them with
+    pub fn set(&mut self, pos: [i32; 3], id: BlockId) {
+        if id != AIR && self.registry.get(id).is_none() {
+            return;
+        }
+        let (key, local) = Self::split(pos);
+        if id == AIR && !self.chunks.contains_key(&key) {
+            return;
+        }
+        let chunk = self.chunks.entry(key).or_insert_with(Chunk::new);
+        let block = &mut chunk.blocks[Chunk::index(local)];
+        if *block == id {
+            return;
+        }
+        match (*block, id) {
+            (AIR, _) => chunk.filled += 1,
+            (_, AIR) => chunk.filled -= 1,
+            _ => (),
+        }
+        *block = id;
+        chunk.dirty = true;
+        if chunk.filled == 0 {
+            self.chunks.remove(&key);
+        }
+        // Faces of the neighbouring chunk touching the block may have been covered or revealed
+        for axis in 0..3 {
+            let side = match local[axis] {
+                0 => -1,
+                l if l == CHUNK_SIZE - 1 => 1,
+                _ => continue,
+            };
+            let mut next = key;
+            next[axis] += side;
+            if let Some(chunk) = self.chunks.get_mut(&next) {
+                chunk.dirty = true;
+            }
+        }
+    }
+    // Rebuilds meshes of the chunks changed since the last call
+    pub fn update_meshes(&mut self) {
+        let dirty: Vec<[i32; 3]> = self
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| chunk.dirty)
+            .map(|(key, _)| *key)
+            .collect();
+        let models: Vec<([i32; 3], Model)> = dirty
+            .into_par_iter()
+            .map(|key| (key, mesh_chunk(self, key)))
+            .collect();
+        for (key, model) in models {
+            let chunk = self.chunks.get_mut(&key).unwrap();
+            chunk.mesh = Some(Object::new(model, [0.0; 3], [0.0; 3], [1.0; 3]));
+            chunk.dirty = false;
+        }
+    }
+    // Meshes of all chunks, for rendering together with other objects
+    pub fn objects(&self) -> impl Iterator<Item = &Object> {
+        self.chunks.values().filter_map(|chunk| chunk.mesh.as_ref())
+    }
+    // First block along the ray
+    pub fn raycast(&self, origin: [f32; 3], dir: [f32; 3], max_dist: f32) -> Option<BlockHit> {
+        self.raycast_where(origin, dir, max_dist, |id| id != AIR)
+    }
+    // First block along the ray that can be stood on
+    pub fn raycast_solid(
+        &self,
+        origin: [f32; 3],
+        dir: [f32; 3],
+        max_dist: f32,
+    ) -> Option<BlockHit> {
+        self.raycast_where(origin, dir, max_dist, |id| self.is_solid(id))
+    }
+    // First block along the ray accepted by hit, walking the grid cell by cell
+    fn raycast_where(
+        &self,
+        origin: [f32; 3],
+        dir: [f32; 3],
+        max_dist: f32,
+        hit: impl Fn(BlockId) -> bool,
+    ) -> Option<BlockHit> {
+        let len = (dir[0] * dir[0] + dir[1] * dir[1] + dir[2] * dir[2]).sqrt();
+        let dir = dir.map(|c| c / len);
+        // Cells are centered on integers
+        let start = origin.map(|c| c + 0.5);
+        let mut cell = start.map(|c| c.floor() as i32);
+        let step = dir.map(|c| if c < 0.0 { -1 } else { 1 });
+        let delta = dir.map(|c| (1.0 / c).abs());
+        // Distance to the next cell boundary on every axis
+        let mut next: [f32; 3] = std::array::from_fn(|i| {
+            if dir[i] > 0.0 {
+                (cell[i] as f32 + 1.0 - start[i]) / dir[i]
+            } else if dir[i] < 0.0 {
+                (start[i] - cell[i] as f32) / -dir[i]
+            } else {
+                f32::INFINITY
+            }
+        });
+        let mut normal = [0; 3];
+        let mut dist = 0.0;
+        loop {
+            if hit(self.get(cell)) {
+                return Some(BlockHit {
+                    pos: cell,
+                    normal,
+                    dist,
+                    point: std::array::from_fn(|i| origin[i] + dir[i] * dist),
+                });
+            }
+            let axis = (0..3).min_by(|&a, &b| next[a].total_cmp(&next[b])).unwrap();
+            if next[axis] > max_dist {
+                return None;
+            }
+            dist = next[axis];
+            next[axis] += delta[axis];
+            cell[axis] += step[axis];
+            normal = [0; 3];
+            normal[axis] = -step[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::block::{BlockType, FaceTexture};
+
+    // World of opaque blocks with the given names and their ids, textured by the placeholder
+    // without loading any file
+    pub(crate) fn world_of(names: &[&str]) -> (VoxelWorld, Vec<BlockId>) {
+        let mut registry = BlockRegistry::new();
+        let ids = names
+            .iter()
+            .map(|name| registry.register(BlockType::new(name, FaceTexture::new("placeholder"))))
+            .collect();
+        let images = HashMap::from([("placeholder".to_string(), Texture::placeholder())]);
+        (VoxelWorld::from_images(registry, &images), ids)
+    }
+
+    fn world() -> VoxelWorld {
+        world_of(&["stone"]).0
+    }
+
+    #[test]
+    fn tile_past_the_strip_uses_placeholder() {
+        // Placeholder strip has 6 tiles
+        let ore = BlockType::new("ore", FaceTexture::tile("placeholder", 9));
+        let images = HashMap::from([("placeholder".to_string(), Texture::placeholder())]);
+        let world = VoxelWorld::from_images(BlockRegistry::new().with_block(ore), &images);
+        let tile = world.atlas().tile("placeholder:9").unwrap();
+        assert_eq!([tile.width, tile.height], [8, 8]);
+    }
+
+    #[test]
+    fn blocks_are_stored_across_chunks() {
+        let mut world = world();
+        for pos in [[0, 0, 0], [-1, 0, 0], [15, 16, -17]] {
+            world.set(pos, 1);
+            assert_eq!(world.get(pos), 1);
+        }
+        assert_eq!(world.get([1, 0, 0]), AIR);
+        assert_eq!(world.chunks.len(), 3);
+        world.set([-1, 0, 0], AIR);
+        assert_eq!(world.get([-1, 0, 0]), AIR);
+        assert_eq!(world.chunks.len(), 2);
+    }
+
+    #[test]
+    fn unregistered_blocks_are_ignored() {
+        let mut world = world();
+        world.set([0, 0, 0], 1);
+        world.set([0, 0, 0], 2);
+        world.set([40, 0, 0], 7);
+        assert_eq!(world.get([0, 0, 0]), 1);
+        assert_eq!(world.get([40, 0, 0]), AIR);
+        assert_eq!(world.chunks.len(), 1);
+    }
+
+    #[test]
+    fn raycast_finds_entered_face() {
+        let mut world = world();
+        world.set([3, 0, 0], 1);
+        world.set([0, -2, 0], 1);
+        let hit = world
+            .raycast([0.0, 0.2, 0.1], [1.0, 0.0, 0.0], 10.0)
+            .unwrap();
+        assert_eq!((hit.pos, hit.normal), ([3, 0, 0], [-1, 0, 0]));
+        assert!((hit.dist - 2.5).abs() < 1e-5);
+        let hit = world
+            .raycast([0.0, 0.0, 0.0], [0.0, -1.0, 0.0], 10.0)
+            .unwrap();
+        assert_eq!((hit.pos, hit.normal), ([0, -2, 0], [0, 1, 0]));
+        assert!(world.raycast([0.0; 3], [1.0, 0.0, 0.0], 2.0).is_none());
+    }
+}
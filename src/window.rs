@@ -1,8 +1,10 @@
 use std::collections::HashSet;
-use std::usize;
+use std::path::Path;
 
 use minifb::Key;
 
+use crate::error::{Error, Result};
+
 #[derive(Clone, Copy, PartialEq)]
 enum State {
     Pressed,
@@ -10,6 +12,16 @@ enum State {
     Released,
 }
 
+// Something frames can be rendered into and input read from, either a real window or an
+// offscreen target
+pub trait Surface {
+    fn framebuffer(&mut self) -> &mut Framebuffer;
+    fn input(&mut self) -> &mut Input;
+    fn should_close(&self) -> bool;
+    // Presents the finished frame and polls input for the next one
+    fn update(&mut self);
+}
+
 pub struct Window {
     window: minifb::Window,
     framebuffer: Framebuffer,
@@ -28,33 +40,39 @@ pub struct Input {
 }
 impl Window {
     pub fn new(name: &str, w: usize, h: usize) -> Self {
+        Self::try_new(name, w, h).unwrap_or_else(|err| panic!("{err}"))
+    }
+    pub fn try_new(name: &str, w: usize, h: usize) -> Result<Self> {
         let options = minifb::WindowOptions {
             resize: true,
             ..Default::default()
         };
 
-        let window = minifb::Window::new(name, w, h, options).expect("Failed to create window");
+        let window = minifb::Window::new(name, w, h, options)
+            .map_err(|err| Error::Window(err.to_string()))?;
         let input = Input::new();
 
-        Window {
+        Ok(Window {
             window,
             input,
             framebuffer: Framebuffer::new(w, h),
-        }
+        })
     }
-    pub fn framebuffer(&mut self) -> &mut Framebuffer {
+}
+impl Surface for Window {
+    fn framebuffer(&mut self) -> &mut Framebuffer {
         &mut self.framebuffer
     }
 
-    pub fn input(&mut self) -> &mut Input {
+    fn input(&mut self) -> &mut Input {
         &mut self.input
     }
 
-    pub fn should_close(&self) -> bool {
+    fn should_close(&self) -> bool {
         !self.window.is_open()
     }
 
-    pub fn update(&mut self) {
+    fn update(&mut self) {
         self.window
             .update_with_buffer(
                 &self.framebuffer.data,
@@ -67,7 +85,8 @@ impl Window {
         if width != self.framebuffer.width || height != self.framebuffer.height {
             self.framebuffer = Framebuffer::new(width, height)
         }
-        self.input.process_input(&self.window);
+        self.input
+            .process_keys(self.window.get_keys().into_iter().collect());
     }
 }
 impl Framebuffer {
@@ -91,17 +110,32 @@ impl Framebuffer {
     pub fn get_pixel(&mut self, x: usize, y: usize) -> u32 {
         self.data[x + y * self.width]
     }
-    pub fn set_pixel_f32(&mut self, x: usize, y: usize, val: f32) {
-        self.data[x + y * self.width] = (val * u32::MAX as f32) as u32;
+    pub fn data(&self) -> &[u32] {
+        &self.data
     }
-    pub fn get_pixel_f32(&mut self, x: usize, y: usize) -> f32 {
-        self.data[x + y * self.width] as f32 / u32::MAX as f32
+    pub fn data_mut(&mut self) -> &mut [u32] {
+        &mut self.data
     }
     pub fn clear(&mut self, col: u32) {
         for i in 0..self.data.len() {
             self.data[i] = col;
         }
     }
+    pub fn to_image(&self) -> image::RgbImage {
+        image::RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let p = self.data[x as usize + y as usize * self.width];
+            image::Rgb([(p >> 16) as u8, (p >> 8) as u8, p as u8])
+        })
+    }
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> image::ImageResult<()> {
+        self.to_image()
+            .save_with_format(path, image::ImageFormat::Png)
+    }
+}
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Input {
     pub fn new() -> Self {
@@ -113,9 +147,8 @@ impl Input {
         }
     }
 
-    pub fn process_input(&mut self, window: &minifb::Window) {
-        let current_keys: HashSet<Key> = window.get_keys().into_iter().collect();
-
+    // Updates key states from the set of keys currently down
+    pub fn process_keys(&mut self, current_keys: HashSet<Key>) {
         self.pressed_keys.clear();
         self.released_keys.clear();
         self.held_keys.clear();
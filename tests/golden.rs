@@ -0,0 +1,183 @@
+// Renders canonical scenes offscreen and compares them against the reference images in
+// tests/golden. Run with BLESS_GOLDEN=1 to write new references after an intended change.
+// On mismatch the rendered image and a diff (mismatching pixels in red) are written into
+// the cargo target directory.
+use graphics_2nd_try::{
+    block::BlockRegistry,
+    camera::Camera,
+    color::{ColorBuffer, ToneMapping},
+    depth::DepthBuffer,
+    headless::Headless,
+    light::Lighting,
+    model::load_model,
+    object::{render_objects, Object},
+    shadow::ShadowMap,
+    voxel::VoxelWorld,
+    window::Surface,
+};
+use image::{Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 120;
+// Largest allowed difference of a single color channel
+const TOLERANCE: u8 = 2;
+
+fn render<'a>(objects: impl IntoIterator<Item = &'a Object> + Clone, camera: &Camera) -> RgbImage {
+    render_lit(objects, camera, Lighting::default())
+}
+
+// Shadows are centered on the origin, where all scenes are placed
+fn render_lit<'a>(
+    objects: impl IntoIterator<Item = &'a Object> + Clone,
+    camera: &Camera,
+    mut lighting: Lighting,
+) -> RgbImage {
+    let mut surface = Headless::new(WIDTH, HEIGHT);
+    let mut depth_buffer = DepthBuffer::new(WIDTH, HEIGHT);
+    let mut color_buffer = ColorBuffer::new(WIDTH, HEIGHT);
+    let view_proj = camera.view_proj(WIDTH, HEIGHT);
+    lighting.eye = camera.eye();
+    if let Some(shadow) = lighting.begin_shadows([0.0; 3]) {
+        for object in objects.clone() {
+            object.render_shadow(shadow);
+        }
+    }
+    render_objects(
+        objects,
+        &mut color_buffer,
+        &mut depth_buffer,
+        &view_proj,
+        &lighting,
+    );
+    let fb = surface.framebuffer();
+    color_buffer.resolve(fb, ToneMapping::default());
+    fb.to_image()
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn check(name: &str, actual: RgbImage) {
+    let path = golden_path(name);
+    if std::env::var_os("BLESS_GOLDEN").is_some() {
+        actual.save(&path).unwrap();
+        return;
+    }
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| panic!("Missing reference {}: {e}", path.display()))
+        .to_rgb8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{name}: size differs from reference"
+    );
+
+    let mut bad = 0;
+    let diff = RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let d = (0..3).map(|c| a[c].abs_diff(e[c])).max().unwrap();
+        if d > TOLERANCE {
+            bad += 1;
+            Rgb([255, 0, 0])
+        } else {
+            // Faded reference so the failing spots can be located
+            Rgb([e[0] / 4, e[1] / 4, e[2] / 4])
+        }
+    });
+    if bad > 0 {
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&out).unwrap();
+        actual.save(out.join(format!("{name}_actual.png"))).unwrap();
+        diff.save(out.join(format!("{name}_diff.png"))).unwrap();
+        panic!(
+            "{name}: {bad} pixels differ from {} by more than {TOLERANCE}, see {}",
+            path.display(),
+            out.display()
+        );
+    }
+}
+
+fn asset(path: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn textured_cube() {
+    let cube = Object::cube(
+        [0.0, 0.0, 0.0],
+        [0.0, 35.0f32.to_radians(), 0.0],
+        [1.0, 1.0, 1.0],
+        Some(&asset("assets/grass.png")),
+    );
+    let camera = Camera::new([0.0, 0.8, -2.5], [20.0f32.to_radians(), 0.0, 0.0]);
+    check("textured_cube", render(&[cube], &camera));
+}
+
+#[test]
+fn shadowed_cube() {
+    let cube = Object::cube(
+        [0.0, 0.0, 0.0],
+        [0.0, 35.0f32.to_radians(), 0.0],
+        [0.6, 0.6, 0.6],
+        None,
+    );
+    let floor = Object::cube([0.0, -0.9, 0.0], [0.0; 3], [3.0, 0.2, 3.0], None);
+    let camera = Camera::new([0.0, 1.5, -4.0], [25.0f32.to_radians(), 0.0, 0.0]);
+    let lighting = Lighting::default().with_shadow(ShadowMap::new(256).with_radius(4.0));
+    check(
+        "shadowed_cube",
+        render_lit(&[cube, floor], &camera, lighting),
+    );
+}
+
+#[test]
+fn ball() {
+    let ball = Object::new(
+        load_model(&asset("assets/ball/ball.gltf")),
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 180.0f32.to_radians()],
+        [1.0, 1.0, 1.0],
+    );
+    let camera = Camera::new([0.0, 0.0, -4.0], [0.0, 0.0, 0.0]);
+    check("ball", render(&[ball], &camera));
+}
+
+#[test]
+fn damaged_helmet() {
+    let helmet = Object::new(
+        load_model(&asset("assets/helmet/DamagedHelmet.gltf")),
+        [0.0, 0.0, 0.0],
+        [90.0f32.to_radians(), 0.0, 180.0f32.to_radians()],
+        [1.0, 1.0, 1.0],
+    );
+    let camera = Camera::new([0.0, 0.0, -3.0], [0.0, 0.0, 0.0]);
+    check("damaged_helmet", render(&[helmet], &camera));
+}
+
+#[test]
+fn voxel_world() {
+    let registry = BlockRegistry::load(&asset("assets/blocks.txt")).unwrap();
+    let [grass, dirt, leaves] = ["grass", "dirt", "leaves"].map(|name| registry.id(name).unwrap());
+    let mut voxels = VoxelWorld::new(registry);
+    // Spans the chunk border at 0
+    for x in -3..3 {
+        for z in -2..2 {
+            voxels.set([x, -1, z], if x == z { dirt } else { grass });
+        }
+    }
+    voxels.set([1, 0, 0], dirt);
+    voxels.set([-1, 0, -1], leaves);
+    voxels.set([-1, 1, -1], leaves);
+    voxels.update_meshes();
+    let camera = Camera::new([0.0, 3.5, -6.0], [30.0f32.to_radians(), -0.3, 0.0]);
+    let chunks: Vec<&Object> = voxels.objects().collect();
+    check("voxel_world", render(chunks.iter().copied(), &camera));
+}